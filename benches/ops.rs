@@ -1,6 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use pixelbuster::pbcore::{parse_ops, process, Space};
-use fastrand;
 
 const NO_OP: &str = "";
 const MINIMAL: &str = "r = r";
@@ -121,6 +120,17 @@ v2 - v4
 c * v2 
 ";
 
+const NOISE: &str = "
+v1 = xnorm
+v1 * 8
+v2 = ynorm
+v2 * 8
+r perlin v1 v2
+g simplex v1 v2
+b worley v1 v2
+a fbm v1 v2 4
+";
+
 macro_rules! bench_op {
     ($cr: expr, $id: literal, $op: expr, $image: expr) => {
         $cr.bench_function(concat!($id, "_parse"), |b| {
@@ -147,6 +157,7 @@ fn ops_main(c: &mut Criterion) {
     bench_op!(c, "space_steps", SPACE_STEPS, image);
     bench_op!(c, "space_maximal", SPACE_MAXIMAL, image);
     bench_op!(c, "filmic_chroma", FILMIC_CHROMA, image);
+    bench_op!(c, "noise", NOISE, image);
 }
 
 criterion_group!(ops, ops_main);
@@ -1,6 +1,6 @@
 use pixelbuster::{
-    pbcore::{parse_ops, process, OpError, Space},
-    pixelbuster, HELP,
+    pbcore::{parse_ops, process_with, OpError, Params, Space},
+    HELP,
 };
 
 use std::path::Path;
@@ -60,6 +60,7 @@ pub struct PBGui {
     help: bool,
     preview: bool,
     pixel_size: usize,
+    seed: u64,
     t_pre: Duration,
     t_parse: Duration,
     t_proc: Duration,
@@ -92,6 +93,13 @@ impl App for PBGui {
                             self.process(&ctx)
                         }
                     });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Seed: ");
+                        if ui.add(DragValue::new(&mut self.seed)).changed() {
+                            self.process(&ctx)
+                        }
+                    });
                 });
                 if ui.button("Open").clicked() {
                     if let Some(path) = FileDialog::new()
@@ -139,12 +147,15 @@ impl App for PBGui {
                                     .save_file()
                                 {
                                     let mut newimg = img.clone();
-                                    pixelbuster(
-                                        &self.code,
-                                        Space::SRGB,
+                                    process_with(
+                                        parse_ops(&self.code, Space::SRGB).0,
                                         &mut newimg,
                                         img.width() as usize,
-                                        Some(self.externals),
+                                        &Params {
+                                            externals: Some(self.externals),
+                                            seed: self.seed,
+                                            ..Default::default()
+                                        },
                                     );
                                     func(DynamicImage::from(newimg), path);
                                 }
@@ -298,6 +309,7 @@ impl PBGui {
             data: None,
             preview: true,
             pixel_size: 1,
+            seed: 0,
             help: false,
             t_pre: Duration::default(),
             t_parse: Duration::default(),
@@ -377,7 +389,16 @@ impl PBGui {
                 // actually process
                 let i_proc = Instant::now();
 
-                process(&ops.0, &mut pixels, width, Some(externals));
+                process_with(
+                    &ops.0,
+                    &mut pixels,
+                    width,
+                    &Params {
+                        externals: Some(externals),
+                        seed: self.seed,
+                        ..Default::default()
+                    },
+                );
 
                 self.t_proc = Instant::now() - i_proc;
 
@@ -0,0 +1,182 @@
+use pixelbuster::{
+    pbcore::{parse_ops_ext, run_pixel, Env, Lint, Operation, Params, PixelState, Space},
+    HELP,
+};
+
+use std::io::{BufRead, Write};
+
+use colcon::str2col;
+
+const USAGE: &str = "\
+Type pixelbuster code to run it on a single pixel.
+Variables, the current space, curves, and gradients carry over from line to line.
+End a line with \\ to continue it on the next.
+
+Commands:
+    .pixel <COLOUR>     Set the pixel. Hex like #ff8800, or channels like '0.2 0.4 0.6 1'.
+                        Channels are sRGB unless prefixed with a space, eg 'oklch 0.7 0.1 60'
+    .space <SPACE>      Convert the pixel to another space
+    .reset              Zero all variables and go back to the starting pixel
+    .language           Print the language reference
+    .help               Print this message
+    .quit               Exit. So does Ctrl+D";
+
+const START: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+fn show(pixel: &[f32; 4], state: &PixelState) {
+    let (name, [c1, c2, c3]) = match state.rgb {
+        Some(rgb) => (rgb.to_string(), ['r', 'g', 'b']),
+        None => (state.space.to_string(), state.space.channels()),
+    };
+    println!(
+        "{}  {} {}  {} {}  {} {}  a {}",
+        name, c1, pixel[0], c2, pixel[1], c3, pixel[2], pixel[3]
+    );
+    for (prefix, vars) in [('v', &state.vars[..9]), ('e', &state.vars[9..])] {
+        let line: Vec<String> = vars
+            .iter()
+            .enumerate()
+            .map(|(n, v)| format!("{}{} {}", prefix, n + 1, v))
+            .collect();
+        println!("{}", line.join("  "));
+    }
+}
+
+/// Brings `pixel` from `from` into whatever space `state` is in
+fn adopt(pixel: &mut [f32; 4], from: Space, state: &mut PixelState) {
+    let (space, rgb) = (state.space, state.rgb.take());
+    state.space = from;
+    match rgb {
+        Some(rgb) => state.convert_rgb(pixel, rgb),
+        None => state.convert(pixel, space),
+    }
+}
+
+fn main() {
+    let params = Params::default();
+    let env = Env {
+        col: 0,
+        row: 0,
+        width: 1,
+        height: 1,
+        mask: 1.0,
+        params: &params,
+    };
+    let mut pixel = START;
+    let mut state = PixelState::new(Space::SRGB, &params);
+
+    println!("{}\n", USAGE);
+    show(&pixel, &state);
+
+    let stdin = std::io::stdin();
+    let mut code = String::new();
+    // curve and gradient declarations, which go before every later line
+    let mut tables = Vec::<String>::new();
+    loop {
+        print!("{}", if code.is_empty() { "> " } else { ". " });
+        std::io::stdout().flush().ok();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        let line = line.trim();
+        if let Some(partial) = line.strip_suffix('\\') {
+            code.push_str(partial);
+            code.push(' ');
+            continue;
+        }
+        code.push_str(line);
+        let input = std::mem::take(&mut code);
+
+        let (command, rest) = input.split_once(' ').unwrap_or((&input, ""));
+        match command {
+            "" => continue,
+            ".quit" | ".exit" => break,
+            ".help" => {
+                println!("{}", USAGE);
+                continue;
+            }
+            ".language" => {
+                println!("{}", HELP);
+                continue;
+            }
+            ".reset" => {
+                let mut fresh = PixelState::new(state.space, &params);
+                fresh.rgb = state.rgb;
+                state = fresh;
+                pixel = START;
+                adopt(&mut pixel, Space::SRGB, &mut state);
+            }
+            ".pixel" => match str2col::<f32, 4>(rest) {
+                Some((space, mut new)) => {
+                    if new[3].is_nan() {
+                        new[3] = 1.0
+                    }
+                    adopt(&mut new, space, &mut state);
+                    pixel = new;
+                }
+                None => {
+                    println!("Could not parse colour '{}'", rest.trim());
+                    continue;
+                }
+            },
+            ".space" => match Space::try_from(rest) {
+                Ok(space) => state.convert(&mut pixel, space),
+                Err(()) => {
+                    println!("Unknown space '{}'", rest.trim());
+                    continue;
+                }
+            },
+            _ if command.starts_with('.') => {
+                println!("Unknown command '{}'. Try .help", command);
+                continue;
+            }
+            _ => {
+                // a new declaration replaces any of the same name
+                let input = input.to_ascii_lowercase();
+                let declared = match input.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["curve" | "gradient", name, ..] => Some(name.to_string()),
+                    _ => None,
+                };
+                let kept: Vec<String> = tables
+                    .iter()
+                    .filter(|t| {
+                        declared.is_none() || t.split_whitespace().nth(1) != declared.as_deref()
+                    })
+                    .cloned()
+                    .collect();
+                // custom RGB spaces and declarations go at the start of the code, on the same line
+                let mut prefix: Vec<String> = state.rgb.iter().map(|rgb| rgb.to_string()).collect();
+                prefix.extend(kept.iter().cloned());
+                prefix.push(input.clone());
+                let (mut ops, errs, warnings) = parse_ops_ext(prefix.join("; "), state.space);
+                // programs start by converting from the space they're given, which is XYZ
+                if let (Some(rgb), Some(first)) = (state.rgb, ops.first_mut()) {
+                    *first = Operation::Rgb(rgb)
+                }
+                // variables carry over from earlier lines, which the linter can't see
+                for w in warnings
+                    .iter()
+                    .filter(|w| !matches!(w.kind, Lint::Unset | Lint::Unused))
+                {
+                    println!("Warning: {}", w);
+                }
+                if !errs.is_empty() {
+                    for e in errs {
+                        println!("{}", e);
+                    }
+                    continue;
+                }
+                if declared.is_some() {
+                    tables = kept;
+                    tables.push(input);
+                }
+                if run_pixel(&ops, &mut pixel, &mut state, &env) {
+                    println!("Stopped by the jump limit");
+                }
+            }
+        }
+        show(&pixel, &state);
+    }
+}
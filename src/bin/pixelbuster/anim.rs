@@ -0,0 +1,177 @@
+//! Rendering a script over several frames of one image.
+
+use pixelbuster::pbcore::{Operation, Params};
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame, ImageFormat, Rgba32FImage,
+};
+
+use super::{apply, save, Args};
+
+/// Fills a printf style frame number like `%d` or `%04d` in `pattern`.
+/// None if there's no such placeholder
+fn sequence_path(pattern: &str, n: usize) -> Option<String> {
+    let start = pattern.find('%')?;
+    let end = start + 1 + pattern[start + 1..].find('d')?;
+    let spec = &pattern[start + 1..end];
+    let width: usize = if spec.is_empty() {
+        0
+    } else if spec.starts_with('0') && spec.chars().all(|c| c.is_ascii_digit()) {
+        spec.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        n,
+        &pattern[end + 1..],
+        width = width
+    ))
+}
+
+/// Frame delays are 1/`denominator` seconds, with the frame rate in hundredths.
+/// None if the rate doesn't fit, as APNG stores it in 16 bits
+fn delay_denominator(fps: f32) -> Option<u16> {
+    let denom = (fps * 100.0).round();
+    (1.0..=u16::MAX as f32)
+        .contains(&denom)
+        .then_some(denom as u16)
+}
+
+fn io_err<E: ToString>(path: &Path) -> impl Fn(E) -> String + '_ {
+    move |e| format!("Could not save {}: {}", path.display(), e.to_string())
+}
+
+/// Renders `frames` frames of `img` into `output`.
+///
+/// `output` with a `%04d` style placeholder writes a numbered sequence,
+/// otherwise it must be a `.gif` or `.png`/`.apng` which is written animated.
+pub fn run(
+    ops: &[Operation],
+    args: &Args,
+    img: &Rgba32FImage,
+    output: &Path,
+    frames: usize,
+) -> Result<(), String> {
+    let (width, height) = (img.width(), img.height());
+    let render = |n: usize| -> Result<Rgba32FImage, String> {
+        let mut frame = img.clone();
+        apply(
+            ops,
+            args,
+            &mut frame,
+            width as usize,
+            &Params {
+                externals: Some(args.externals),
+                seed: args.seed,
+                jump_limit: args.jump_limit,
+                jump_limit_error: args.jump_limit_error,
+                lock_alpha: args.lock_alpha,
+                frame: n,
+                time: n as f32 / args.fps,
+                frames,
+                ..Default::default()
+            },
+            &format!("frame {}", n),
+        )?;
+        Ok(frame)
+    };
+
+    let pattern = output.to_string_lossy();
+    if sequence_path(&pattern, 0).is_some() {
+        for n in 0..frames {
+            let path = sequence_path(&pattern, n).unwrap();
+            let path = Path::new(&path);
+            let format = args
+                .format
+                .or(ImageFormat::from_path(path).ok())
+                .unwrap_or(ImageFormat::Png);
+            save(render(n)?, path, format, args.depth)?;
+        }
+        eprintln!("{} frames -> {}", frames, output.display());
+        return Ok(());
+    }
+
+    let ext = output
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    if !matches!(ext.as_deref(), Some("gif" | "png" | "apng")) {
+        return Err(format!(
+            "Can't animate {}. Use .gif, .png, or a numbered pattern like out_%04d.png",
+            output.display()
+        ));
+    }
+    let denom = delay_denominator(args.fps).ok_or(format!(
+        "Can't animate at {} fps, use 0.01 to 655.35",
+        args.fps
+    ))?;
+    let count = u32::try_from(frames).map_err(|_| format!("Too many frames, {}", frames))?;
+    let file = File::create(output).map_err(io_err(output))?;
+    if ext.as_deref() == Some("gif") {
+        let mut encoder = GifEncoder::new(BufWriter::new(file));
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(io_err(output))?;
+        for n in 0..frames {
+            encoder
+                .encode_frame(Frame::from_parts(
+                    DynamicImage::from(render(n)?).into_rgba8(),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(100_000, denom as u32),
+                ))
+                .map_err(io_err(output))?;
+        }
+    } else {
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(count, 0).map_err(io_err(output))?;
+        encoder
+            .set_frame_delay(100, denom)
+            .map_err(io_err(output))?;
+        let mut writer = encoder.write_header().map_err(io_err(output))?;
+        for n in 0..frames {
+            writer
+                .write_image_data(&DynamicImage::from(render(n)?).into_rgba8())
+                .map_err(io_err(output))?;
+        }
+        writer.finish().map_err(io_err(output))?;
+    }
+    eprintln!("{} frames -> {}", frames, output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays() {
+        assert_eq!(delay_denominator(24.0), Some(2400));
+        assert_eq!(delay_denominator(29.97), Some(2997));
+        assert_eq!(delay_denominator(655.35), Some(65535));
+        assert_eq!(delay_denominator(700.0), None);
+        assert_eq!(delay_denominator(0.001), None);
+        assert_eq!(delay_denominator(0.0), None);
+        assert_eq!(delay_denominator(-24.0), None);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(
+            sequence_path("out_%04d.png", 7).as_deref(),
+            Some("out_0007.png")
+        );
+        assert_eq!(sequence_path("%d.png", 12).as_deref(), Some("12.png"));
+        assert_eq!(sequence_path("out.png", 1), None);
+        assert_eq!(sequence_path("out_%4d.png", 1), None);
+    }
+}
@@ -0,0 +1,624 @@
+use pixelbuster::{
+    pbcore::{
+        bake, parse_ops_ext, process_profile, process_with, Operation, Params, Profile, Space,
+    },
+    HELP,
+};
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use colcon::convert_space_sliced;
+use image::{io::Reader, DynamicImage, ImageFormat, Rgba32FImage};
+
+mod anim;
+mod pipe;
+use pipe::RawFormat;
+
+const USAGE: &str = "\
+Usage: pixelbuster [OPTIONS] <SCRIPT> <INPUT>... -o <OUTPUT>
+       pixelbuster [OPTIONS] -c <CODE> <INPUT>... -o <OUTPUT>
+       pixelbuster [OPTIONS] <SCRIPT> < FRAMES > FRAMES
+       pixelbuster [OPTIONS] <SCRIPT> <INPUT> -o <OUTPUT> --frames <N>
+       pixelbuster [OPTIONS] <SCRIPT> --bake <LUT>
+
+Applies a pixelbuster script to every input image.
+
+With no inputs or output, frames are streamed from stdin to stdout in the format they came in.
+PPM, PAM, and PFM streams are detected automatically. Headerless frames need --raw.
+The 'frame' source counts up from 0 for each frame.
+
+With --frames, one input is rendered N times as an animated .gif or .png,
+or a numbered sequence when OUTPUT has a placeholder like 'out_%04d.png'.
+
+With --bake, the script is run over a lattice of sRGB colours and saved as a 3D LUT
+for video tools: .cube, .3dl, or a Hald CLUT .png. Scripts reading the position,
+'rand', the mask, the frame or time, or using blend can't be baked.
+
+Arguments:
+    <SCRIPT>    File containing the code to run
+    <INPUT>     Image file, directory of images, or a quoted wildcard pattern like 'shots/*.png'
+
+Options:
+    -c, --code <CODE>       Code to run instead of a script file
+    -o, --output <OUTPUT>   Output file, or directory when there are several inputs
+    --e1 <VALUE> ... --e9 <VALUE>
+                            Starting value of an external variable. Also accepts --e1=VALUE
+    -p, --param <NAME=VALUE>
+                            Starting value of an external the script names with a comment
+                            like '# e1: strength'. e1 through e9 work as names too
+    --space <SPACE>         Space the script starts in. Default srgb
+    --seed <SEED>           Seed for 'rand' and noise. Default 0
+    -f, --format <FORMAT>   Output format by extension, eg 'png'. Default from OUTPUT or the input
+    -d, --depth <BITS>      Output bits per channel, 8 16 or 32. Default 32 for exr/hdr, else 8
+    --raw <WxH>             Stream headerless frames of this size
+    --pix-fmt <FORMAT>      Layout of --raw frames, named like ffmpeg's. Default rgb24
+                            rgb24 rgba rgb48le rgba64le rgbf32le rgbaf32le
+    --frames <N>            Render N frames of a single input
+    --fps <FPS>             Frame rate for the 'time' source and animations. Default 24,
+                            animations take 0.01 to 655.35
+    --bake <LUT>            Save the script as a 3D LUT instead of processing images
+    --lut-size <N>          Points along each axis of a baked LUT. Default 33, or 64 for Hald
+                            CLUTs which need a square like 16, 36, or 64
+    --jump-limit <N>        Jumps each pixel may take before it's stopped. Default 100
+    --jump-limit-error      Fail instead of warning when a pixel hits the jump limit
+    --premultiplied         Colour in the inputs is multiplied by alpha, as from most compositors.
+                            Unpremultiplied for the script and premultiplied again after
+    --lock-alpha            Keep alpha as it was, whatever the script does
+    --profile               Print how often each operation ran and where the time went
+    --language              Print the language reference
+    -h, --help              Print this message";
+
+/// Extensions picked up when an input is a directory or pattern
+const IMAGE_EXTS: &[&str] = &[
+    "avif", "bmp", "dds", "exr", "ff", "gif", "hdr", "ico", "jpeg", "jpg", "png", "pnm", "qoi",
+    "tga", "tif", "tiff", "webp",
+];
+
+struct Args {
+    code: String,
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    externals: [f32; 9],
+    space: Space,
+    seed: u64,
+    format: Option<ImageFormat>,
+    depth: Option<u8>,
+    raw: Option<(usize, usize, RawFormat)>,
+    frames: Option<usize>,
+    fps: f32,
+    bake: Option<PathBuf>,
+    lut_size: Option<usize>,
+    jump_limit: Option<usize>,
+    jump_limit_error: bool,
+    premultiplied: bool,
+    lock_alpha: bool,
+    profile: bool,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut script = None;
+    let mut code = None;
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut externals = [0.0; 9];
+    let mut space = Space::SRGB;
+    let mut seed = 0;
+    let mut format = None;
+    let mut depth = None;
+    let mut raw_size = None;
+    let mut pix_fmt = RawFormat::Rgb24;
+    let mut frames = None;
+    let mut fps = 24.0;
+    let mut bake = None;
+    let mut lut_size = None;
+    let mut jump_limit = None;
+    let mut jump_limit_error = false;
+    let mut premultiplied = false;
+    let mut lock_alpha = false;
+    let mut profile = false;
+    let mut params = Vec::new();
+
+    while let Some(arg) = args.next() {
+        // allow both '--opt value' and '--opt=value'
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or(format!("Missing value for {}", flag))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(None);
+            }
+            "--language" => {
+                println!("{}", HELP);
+                return Ok(None);
+            }
+            "-c" | "--code" => code = Some(value()?),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--space" => {
+                let v = value()?;
+                space = Space::try_from(v.as_str()).map_err(|_| format!("Invalid space {}", v))?
+            }
+            "--seed" => {
+                let v = value()?;
+                seed = v.parse().map_err(|_| format!("Invalid seed {}", v))?
+            }
+            "-f" | "--format" => {
+                let v = value()?;
+                format =
+                    Some(ImageFormat::from_extension(&v).ok_or(format!("Unknown format {}", v))?)
+            }
+            "-d" | "--depth" => {
+                let v = value()?;
+                depth = match v.as_str() {
+                    "8" => Some(8),
+                    "16" => Some(16),
+                    "32" => Some(32),
+                    _ => return Err(format!("Invalid depth {}", v)),
+                }
+            }
+            "--raw" => {
+                let v = value()?;
+                raw_size = match v.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+                    Some((Ok(w), Ok(h))) if w > 0 && h > 0 => Some((w, h)),
+                    _ => return Err(format!("Invalid size {}, expected WxH", v)),
+                }
+            }
+            "--frames" => {
+                let v = value()?;
+                frames = match v.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("Invalid frame count {}", v)),
+                }
+            }
+            "--fps" => {
+                let v = value()?;
+                fps = match v.parse::<f32>() {
+                    Ok(f) if f > 0.0 && f.is_finite() => f,
+                    _ => return Err(format!("Invalid frame rate {}", v)),
+                }
+            }
+            "--bake" => bake = Some(PathBuf::from(value()?)),
+            "--lut-size" => {
+                let v = value()?;
+                lut_size = match v.parse() {
+                    Ok(n) if n >= 2 => Some(n),
+                    _ => return Err(format!("Invalid LUT size {}", v)),
+                }
+            }
+            "--jump-limit" => {
+                let v = value()?;
+                jump_limit = Some(v.parse().map_err(|_| format!("Invalid jump limit {}", v))?)
+            }
+            "--jump-limit-error" => jump_limit_error = true,
+            "--premultiplied" => premultiplied = true,
+            "--lock-alpha" => lock_alpha = true,
+            "--profile" => profile = true,
+            "-p" | "--param" => params.push(value()?),
+            "--pix-fmt" => pix_fmt = RawFormat::try_from(value()?.as_str())?,
+            f if f.len() == 4 && f.starts_with("--e") => {
+                let n = match f[3..].parse::<usize>() {
+                    Ok(n @ 1..=9) => n,
+                    _ => return Err(format!("Unknown option {}", f)),
+                };
+                let v = value()?;
+                externals[n - 1] = v
+                    .parse()
+                    .map_err(|_| format!("Invalid value {} for {}", v, f))?
+            }
+            f if f.starts_with('-') && f.len() > 1 => return Err(format!("Unknown option {}", f)),
+            _ => {
+                if code.is_none() && script.is_none() {
+                    script = Some(arg)
+                } else {
+                    inputs.push(arg)
+                }
+            }
+        }
+    }
+
+    // -c given after a positional means the first positional was an input
+    if code.is_some() {
+        if let Some(s) = script.take() {
+            inputs.insert(0, s)
+        }
+    }
+    let code = match (code, script) {
+        (Some(c), _) => c,
+        (None, Some(s)) => {
+            std::fs::read_to_string(&s).map_err(|e| format!("Could not read {}: {}", s, e))?
+        }
+        (None, None) => return Err(format!("No script given\n\n{}", USAGE)),
+    };
+
+    let names = param_names(&code);
+    for param in params {
+        let (name, v) = param
+            .split_once('=')
+            .ok_or(format!("Invalid param {}, expected NAME=VALUE", param))?;
+        let n = match name.strip_prefix('e').and_then(|n| n.parse::<usize>().ok()) {
+            Some(n @ 1..=9) => n,
+            _ => {
+                names
+                    .iter()
+                    .position(|n| n.as_deref() == Some(name))
+                    .ok_or(format!("The script has no param named {}", name))?
+                    + 1
+            }
+        };
+        externals[n - 1] = v
+            .parse()
+            .map_err(|_| format!("Invalid value {} for {}", v, name))?
+    }
+
+    let mut expanded = Vec::new();
+    for input in inputs {
+        expanded.extend(expand(&input)?);
+    }
+
+    Ok(Some(Args {
+        code,
+        inputs: expanded,
+        output,
+        externals,
+        space,
+        seed,
+        format,
+        depth,
+        raw: raw_size.map(|(w, h)| (w, h, pix_fmt)),
+        frames,
+        fps,
+        bake,
+        lut_size,
+        jump_limit,
+        jump_limit_error,
+        premultiplied,
+        lock_alpha,
+        profile,
+    }))
+}
+
+/// Names given to externals by comments like '# e1: strength'
+fn param_names(code: &str) -> [Option<String>; 9] {
+    let mut names: [Option<String>; 9] = Default::default();
+    for line in code.lines().flat_map(|l| l.split(';')) {
+        let comment = match line.trim().strip_prefix('#') {
+            Some(c) => c,
+            None => continue,
+        };
+        if let Some((var, name)) = comment.split_once(':') {
+            let name = name.trim();
+            match var.trim().strip_prefix('e').map(|n| n.parse::<usize>()) {
+                Some(Ok(n @ 1..=9)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                    names[n - 1] = Some(name.to_string())
+                }
+                _ => (),
+            }
+        }
+    }
+    names
+}
+
+/// Simple '*' and '?' matching
+fn wildcard(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard(&pattern[1..], name) || (!name.is_empty() && wildcard(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+fn is_image(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| IMAGE_EXTS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Turns an input argument into the image files it names
+fn expand(input: &str) -> Result<Vec<PathBuf>, String> {
+    let path = PathBuf::from(input);
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let (dir, pattern) = if path.is_dir() {
+        (path.clone(), "*")
+    } else if name.contains(['*', '?']) {
+        match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => (p.to_path_buf(), name),
+            _ => (PathBuf::from("."), name),
+        }
+    } else if path.is_file() {
+        return Ok(vec![path]);
+    } else {
+        return Err(format!("Input {} not found", input));
+    };
+
+    let mut found: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .map_err(|e| format!("Could not read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| wildcard(pattern.as_bytes(), n.as_bytes()))
+                && is_image(p)
+        })
+        .collect();
+    found.sort();
+    if found.is_empty() {
+        Err(format!("No images match {}", input))
+    } else {
+        Ok(found)
+    }
+}
+
+fn save(
+    pixels: Rgba32FImage,
+    path: &Path,
+    format: ImageFormat,
+    depth: Option<u8>,
+) -> Result<(), String> {
+    let depth = depth.unwrap_or(match format {
+        ImageFormat::OpenExr | ImageFormat::Hdr => 32,
+        _ => 8,
+    });
+    let alpha = !matches!(format, ImageFormat::Jpeg | ImageFormat::Hdr);
+    let img = DynamicImage::from(pixels);
+    let img = match (depth, alpha) {
+        (8, true) => DynamicImage::from(img.into_rgba8()),
+        (8, false) => DynamicImage::from(img.into_rgb8()),
+        (16, true) => DynamicImage::from(img.into_rgba16()),
+        (16, false) => DynamicImage::from(img.into_rgb16()),
+        (_, true) => img,
+        (_, false) => DynamicImage::from(img.into_rgb32f()),
+    };
+    img.save_with_format(path, format)
+        .map_err(|e| format!("Could not save {}: {}", path.display(), e))
+}
+
+fn load(path: &Path) -> Result<(Rgba32FImage, Option<ImageFormat>), String> {
+    let reader = Reader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+    let format = reader.format();
+    let img = reader
+        .decode()
+        .map_err(|e| format!("Could not decode {}: {}", path.display(), e))?
+        .into_rgba32f();
+    Ok((img, format))
+}
+
+fn report(name: &str, ops: &[Operation], profile: &Profile) {
+    eprintln!(
+        "{}: {} pixels, {} hit the jump limit, {:.1?} in conversions, {:.1?} in arithmetic",
+        name, profile.pixels, profile.jump_limited, profile.conversion, profile.arithmetic
+    );
+    for (count, op) in profile.counts.iter().zip(ops) {
+        eprintln!("{:>12} {}", count, op);
+    }
+}
+
+/// Runs `ops` over sRGB `pixels` in the script's space.
+/// The profile and any pixels hitting the jump limit are reported under `name`,
+/// which is an error with `Params::jump_limit_error`
+fn apply(
+    ops: &[Operation],
+    args: &Args,
+    pixels: &mut [f32],
+    width: usize,
+    params: &Params,
+    name: &str,
+) -> Result<(), String> {
+    // straighten colour before the space conversion, which isn't linear
+    if args.premultiplied {
+        for px in pixels.chunks_exact_mut(4).filter(|px| px[3] != 0.0) {
+            let alpha = px[3];
+            px[..3].iter_mut().for_each(|c| *c /= alpha)
+        }
+    }
+    convert_space_sliced::<f32, 4>(Space::SRGB, args.space, pixels);
+    let limited = if args.profile {
+        let profile = process_profile(ops, pixels, width, params);
+        report(name, ops, &profile);
+        profile.jump_limited
+    } else {
+        process_with(ops, pixels, width, params)
+    };
+    if limited > 0 && params.jump_limit_error {
+        return Err(format!("{} pixels of {} hit the jump limit", limited, name));
+    } else if limited > 0 {
+        eprintln!("Warning: {} pixels of {} hit the jump limit", limited, name);
+    }
+    convert_space_sliced::<f32, 4>(args.space, Space::SRGB, pixels);
+    if args.premultiplied {
+        for px in pixels.chunks_exact_mut(4) {
+            let alpha = px[3];
+            px[..3].iter_mut().for_each(|c| *c *= alpha)
+        }
+    }
+    Ok(())
+}
+
+/// Bakes `ops` into a LUT file, in the format its extension names
+fn save_lut(ops: &[Operation], args: &Args, path: &Path) -> Result<(), String> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let hald = match ext.as_deref() {
+        Some("cube" | "3dl") => false,
+        Some("png") => true,
+        _ => {
+            return Err(format!(
+                "Unknown LUT format for {}, use .cube, .3dl, or .png",
+                path.display()
+            ))
+        }
+    };
+    let size = args.lut_size.unwrap_or(if hald { 64 } else { 33 });
+    let params = Params {
+        externals: Some(args.externals),
+        seed: args.seed,
+        jump_limit: args.jump_limit,
+        ..Default::default()
+    };
+    let lut = bake(ops, size, Space::SRGB, &params).map_err(|e| e.to_string())?;
+
+    let written = match ext.as_deref() {
+        Some("cube") => std::fs::write(path, lut.to_cube()).map_err(|e| e.to_string()),
+        Some("3dl") => std::fs::write(path, lut.to_3dl()).map_err(|e| e.to_string()),
+        _ => {
+            let level = lut.hald_level().ok_or(format!(
+                "Hald CLUTs need a square size like 16, 36, or 64, not {}",
+                size
+            ))?;
+            let side = (size * level) as u32;
+            let pixels = lut
+                .table
+                .iter()
+                .flat_map(|c| c.map(|c| (c.clamp(0.0, 1.0) * 65535.0).round() as u16))
+                .collect();
+            let img = image::ImageBuffer::<image::Rgb<u16>, Vec<u16>>::from_raw(side, side, pixels)
+                .ok_or("Hald CLUT doesn't fit its image")?;
+            img.save_with_format(path, ImageFormat::Png)
+                .map_err(|e| e.to_string())
+        }
+    };
+    written.map_err(|e| format!("Could not save {}: {}", path.display(), e))?;
+    eprintln!("{}x{}x{} LUT -> {}", size, size, size, path.display());
+    Ok(())
+}
+
+/// Format a batch saves `input` as, if it's known before loading
+fn batch_format(args: &Args, input: &Path) -> Option<ImageFormat> {
+    args.format.or(ImageFormat::from_path(input).ok())
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let (ops, errs, warnings) = parse_ops_ext(&args.code, args.space);
+    for w in warnings {
+        eprintln!("Warning: {}", w);
+    }
+    if !errs.is_empty() {
+        return Err(errs
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>()
+            .join("\n"));
+    }
+
+    if let Some(path) = &args.bake {
+        if !args.inputs.is_empty() {
+            return Err("--bake takes no inputs".to_string());
+        }
+        return save_lut(&ops, args, path);
+    }
+    if args.inputs.is_empty() && args.output.is_none() {
+        return pipe::run(&ops, args);
+    } else if args.inputs.is_empty() {
+        return Err(format!("No inputs given\n\n{}", USAGE));
+    }
+    let output = args.output.as_ref().ok_or("No output given")?;
+
+    if let Some(frames) = args.frames {
+        if args.inputs.len() != 1 {
+            return Err("--frames takes exactly one input".to_string());
+        }
+        return anim::run(&ops, args, &load(&args.inputs[0])?.0, output, frames);
+    }
+
+    let batch = args.inputs.len() > 1 || output.is_dir();
+    if batch {
+        // inputs from different directories can share a name
+        let mut seen: Vec<(&Path, Option<ImageFormat>)> = Vec::new();
+        for input in args.inputs.iter() {
+            let format = batch_format(args, input);
+            let stem = input.file_stem().unwrap_or_default();
+            if let Some((other, _)) = seen.iter().find(|(p, f)| {
+                p.file_stem().unwrap_or_default() == stem
+                    && (*f == format || f.is_none() || format.is_none())
+            }) {
+                return Err(format!(
+                    "{} and {} would both be saved as {} in {}",
+                    other.display(),
+                    input.display(),
+                    stem.to_string_lossy(),
+                    output.display()
+                ));
+            }
+            seen.push((input, format));
+        }
+        std::fs::create_dir_all(output)
+            .map_err(|e| format!("Could not create {}: {}", output.display(), e))?;
+    }
+
+    let params = Params {
+        externals: Some(args.externals),
+        seed: args.seed,
+        jump_limit: args.jump_limit,
+        jump_limit_error: args.jump_limit_error,
+        lock_alpha: args.lock_alpha,
+        ..Default::default()
+    };
+
+    for input in args.inputs.iter() {
+        let (mut img, in_format) = load(input)?;
+
+        let format = if batch {
+            batch_format(args, input)
+        } else {
+            args.format.or(ImageFormat::from_path(output).ok())
+        }
+        .or(in_format)
+        .unwrap_or(ImageFormat::Png);
+        let path = if batch {
+            output
+                .join(input.file_stem().unwrap_or_default())
+                .with_extension(format.extensions_str()[0])
+        } else {
+            output.clone()
+        };
+
+        let width = img.width() as usize;
+        apply(
+            &ops,
+            args,
+            &mut img,
+            width,
+            &params,
+            &input.display().to_string(),
+        )?;
+
+        save(img, &path, format, args.depth)?;
+        eprintln!("{} -> {}", input.display(), path.display());
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => match run(&args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::FAILURE
+            }
+        },
+        Ok(None) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,534 @@
+//! Streaming frames through stdin and stdout.
+
+use pixelbuster::pbcore::{Operation, Params};
+
+use std::io::{self, BufRead, BufWriter, ErrorKind, Read, Write};
+
+use super::{apply, Args};
+
+/// Pixel layouts for headerless frames, named like ffmpeg's `-pix_fmt`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RawFormat {
+    Rgb24,
+    Rgba,
+    Rgb48,
+    Rgba64,
+    RgbF32,
+    RgbaF32,
+}
+
+impl TryFrom<&str> for RawFormat {
+    type Error = String;
+    fn try_from(value: &str) -> Result<Self, String> {
+        match value {
+            "rgb24" => Ok(RawFormat::Rgb24),
+            "rgba" => Ok(RawFormat::Rgba),
+            "rgb48le" => Ok(RawFormat::Rgb48),
+            "rgba64le" => Ok(RawFormat::Rgba64),
+            "rgbf32le" => Ok(RawFormat::RgbF32),
+            "rgbaf32le" => Ok(RawFormat::RgbaF32),
+            _ => Err(format!("Unknown pixel format {}", value)),
+        }
+    }
+}
+
+impl RawFormat {
+    fn channels(self) -> usize {
+        match self {
+            RawFormat::Rgb24 | RawFormat::Rgb48 | RawFormat::RgbF32 => 3,
+            RawFormat::Rgba | RawFormat::Rgba64 | RawFormat::RgbaF32 => 4,
+        }
+    }
+
+    fn encoding(self) -> Encoding {
+        match self {
+            RawFormat::Rgb24 | RawFormat::Rgba => Encoding::U8(255),
+            RawFormat::Rgb48 | RawFormat::Rgba64 => Encoding::U16LE(65535),
+            RawFormat::RgbF32 | RawFormat::RgbaF32 => Encoding::F32LE,
+        }
+    }
+}
+
+/// How each sample is stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    U8(u16),
+    U16BE(u16),
+    U16LE(u16),
+    F32LE,
+    F32BE,
+}
+
+impl Encoding {
+    fn netpbm(maxval: u16) -> Self {
+        if maxval < 256 {
+            Encoding::U8(maxval)
+        } else {
+            Encoding::U16BE(maxval)
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Encoding::U8(_) => 1,
+            Encoding::U16BE(_) | Encoding::U16LE(_) => 2,
+            Encoding::F32LE | Encoding::F32BE => 4,
+        }
+    }
+
+    fn decode(self, b: &[u8]) -> f32 {
+        match self {
+            Encoding::U8(max) => b[0] as f32 / max as f32,
+            Encoding::U16BE(max) => u16::from_be_bytes([b[0], b[1]]) as f32 / max as f32,
+            Encoding::U16LE(max) => u16::from_le_bytes([b[0], b[1]]) as f32 / max as f32,
+            Encoding::F32LE => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            Encoding::F32BE => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    }
+
+    fn encode(self, v: f32, out: &mut Vec<u8>) {
+        let int = |max: u16| (v.clamp(0.0, 1.0) * max as f32).round() as u16;
+        match self {
+            Encoding::U8(max) => out.push(int(max) as u8),
+            Encoding::U16BE(max) => out.extend_from_slice(&int(max).to_be_bytes()),
+            Encoding::U16LE(max) => out.extend_from_slice(&int(max).to_le_bytes()),
+            Encoding::F32LE => out.extend_from_slice(&v.to_le_bytes()),
+            Encoding::F32BE => out.extend_from_slice(&v.to_be_bytes()),
+        }
+    }
+}
+
+/// Container of a frame. Output frames are written in the same format they came in.
+#[derive(Clone, Debug, PartialEq)]
+enum Format {
+    Raw(RawFormat),
+    Ppm(u16),
+    Pam {
+        depth: usize,
+        maxval: u16,
+        tupltype: String,
+    },
+    Pfm {
+        channels: usize,
+        scale: f32,
+    },
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    format: Format,
+    /// Always RGBA
+    pixels: Vec<f32>,
+}
+
+/// Reads one whitespace separated netpbm header token, skipping comments.
+/// Consumes the single whitespace byte after it.
+fn token<R: BufRead>(r: &mut R) -> io::Result<Option<String>> {
+    let mut tok = Vec::new();
+    let mut comment = false;
+    for byte in r.bytes() {
+        let byte = byte?;
+        if comment {
+            comment = byte != b'\n';
+        } else if byte == b'#' && tok.is_empty() {
+            comment = true;
+        } else if byte.is_ascii_whitespace() {
+            if !tok.is_empty() {
+                break;
+            }
+        } else {
+            tok.push(byte)
+        }
+    }
+    if tok.is_empty() {
+        Ok(None)
+    } else {
+        String::from_utf8(tok)
+            .map(Some)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+fn invalid<E: ToString>(e: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+fn number<R: BufRead, T: std::str::FromStr>(r: &mut R) -> io::Result<T> {
+    token(r)?
+        .and_then(|t| t.parse().ok())
+        .ok_or(invalid("Invalid header"))
+}
+
+/// Largest frame accepted, in pixels. 32768x32768 is already 16 GiB once processed
+const MAX_PIXELS: usize = 1 << 30;
+
+/// Refuses headers that can't describe a frame
+fn check(width: usize, height: usize, maxval: u16) -> io::Result<()> {
+    let pixels = width.saturating_mul(height);
+    if width == 0 || height == 0 || pixels > MAX_PIXELS {
+        Err(invalid(format!("Invalid frame size {}x{}", width, height)))
+    } else if maxval == 0 {
+        Err(invalid("Invalid maxval 0"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Reads the samples of a frame into RGBA, a row at a time
+/// so a short stream fails before much is allocated
+fn samples<R: Read>(
+    r: &mut R,
+    width: usize,
+    height: usize,
+    channels: usize,
+    encoding: Encoding,
+) -> io::Result<Vec<f32>> {
+    check(width, height, 1)?;
+    let mut row = vec![0; width * channels * encoding.size()];
+    let mut pixels = Vec::with_capacity(width * 4);
+    for _ in 0..height {
+        r.read_exact(&mut row)?;
+        for px in row.chunks_exact(channels * encoding.size()) {
+            let mut vals = px.chunks_exact(encoding.size()).map(|b| encoding.decode(b));
+            match channels {
+                1 | 2 => {
+                    let l = vals.next().unwrap();
+                    pixels.extend_from_slice(&[l, l, l, vals.next().unwrap_or(1.0)])
+                }
+                _ => {
+                    pixels.extend(vals.by_ref().take(3));
+                    pixels.push(vals.next().unwrap_or(1.0))
+                }
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+/// Writes RGBA pixels as `channels` samples
+fn write_samples(pixels: &[f32], channels: usize, encoding: Encoding, out: &mut Vec<u8>) {
+    for px in pixels.chunks_exact(4) {
+        match channels {
+            1 | 2 => {
+                // Rec. 709 luma
+                encoding.encode(0.2126 * px[0] + 0.7152 * px[1] + 0.0722 * px[2], out);
+                if channels == 2 {
+                    encoding.encode(px[3], out)
+                }
+            }
+            _ => px[..channels.min(4)]
+                .iter()
+                .for_each(|v| encoding.encode(*v, out)),
+        }
+    }
+}
+
+/// Returns None at a clean end of stream
+fn read_frame<R: BufRead>(
+    r: &mut R,
+    raw: Option<(usize, usize, RawFormat)>,
+) -> io::Result<Option<Frame>> {
+    if let Some((width, height, format)) = raw {
+        if r.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(Frame {
+            width,
+            height,
+            pixels: samples(r, width, height, format.channels(), format.encoding())?,
+            format: Format::Raw(format),
+        }));
+    }
+
+    let magic = match token(r)? {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+    match magic.as_str() {
+        "P6" => {
+            let (width, height, maxval) = (number(r)?, number(r)?, number(r)?);
+            check(width, height, maxval)?;
+            Ok(Some(Frame {
+                width,
+                height,
+                pixels: samples(r, width, height, 3, Encoding::netpbm(maxval))?,
+                format: Format::Ppm(maxval),
+            }))
+        }
+        "P7" => {
+            let (mut width, mut height, mut depth, mut maxval) = (0, 0, 0, 255);
+            let mut tupltype = String::new();
+            loop {
+                match token(r)?.as_deref() {
+                    Some("WIDTH") => width = number(r)?,
+                    Some("HEIGHT") => height = number(r)?,
+                    Some("DEPTH") => depth = number(r)?,
+                    Some("MAXVAL") => maxval = number(r)?,
+                    Some("TUPLTYPE") => tupltype = token(r)?.unwrap_or_default(),
+                    Some("ENDHDR") => break,
+                    _ => return Err(invalid("Invalid PAM header")),
+                }
+            }
+            if !(1..=4).contains(&depth) {
+                return Err(invalid(format!("Unsupported PAM depth {}", depth)));
+            }
+            check(width, height, maxval)?;
+            Ok(Some(Frame {
+                width,
+                height,
+                pixels: samples(r, width, height, depth, Encoding::netpbm(maxval))?,
+                format: Format::Pam {
+                    depth,
+                    maxval,
+                    tupltype,
+                },
+            }))
+        }
+        "PF" | "Pf" => {
+            let channels = if magic == "PF" { 3 } else { 1 };
+            let (width, height, scale): (usize, usize, f32) = (number(r)?, number(r)?, number(r)?);
+            check(width, height, 1)?;
+            if scale == 0.0 || !scale.is_finite() {
+                return Err(invalid(format!("Invalid PFM scale {}", scale)));
+            }
+            let encoding = if scale < 0.0 {
+                Encoding::F32LE
+            } else {
+                Encoding::F32BE
+            };
+            let mut pixels = samples(r, width, height, channels, encoding)?;
+            // PFM rows go bottom to top
+            pixels = pixels
+                .chunks_exact(width * 4)
+                .rev()
+                .flatten()
+                .copied()
+                .collect();
+            // the sign is the byte order, the size scales the samples
+            for px in pixels.chunks_exact_mut(4) {
+                px[..3].iter_mut().for_each(|c| *c *= scale.abs())
+            }
+            Ok(Some(Frame {
+                width,
+                height,
+                pixels,
+                format: Format::Pfm { channels, scale },
+            }))
+        }
+        m => Err(invalid(format!(
+            "Unknown stream format '{}'. Use --raw for headerless frames",
+            m
+        ))),
+    }
+}
+
+fn write_frame<W: Write>(w: &mut W, frame: &Frame) -> io::Result<()> {
+    let mut out = Vec::with_capacity(frame.pixels.len() * 4);
+    match &frame.format {
+        Format::Raw(format) => write_samples(
+            &frame.pixels,
+            format.channels(),
+            format.encoding(),
+            &mut out,
+        ),
+        Format::Ppm(maxval) => {
+            write!(out, "P6\n{} {}\n{}\n", frame.width, frame.height, maxval)?;
+            write_samples(&frame.pixels, 3, Encoding::netpbm(*maxval), &mut out)
+        }
+        Format::Pam {
+            depth,
+            maxval,
+            tupltype,
+        } => {
+            write!(
+                out,
+                "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\n",
+                frame.width, frame.height, depth, maxval
+            )?;
+            if !tupltype.is_empty() {
+                writeln!(out, "TUPLTYPE {}", tupltype)?;
+            }
+            writeln!(out, "ENDHDR")?;
+            write_samples(&frame.pixels, *depth, Encoding::netpbm(*maxval), &mut out)
+        }
+        Format::Pfm { channels, scale } => {
+            let magic = if *channels == 3 { "PF" } else { "Pf" };
+            write!(
+                out,
+                "{}\n{} {}\n{}\n",
+                magic, frame.width, frame.height, scale
+            )?;
+            let encoding = if *scale < 0.0 {
+                Encoding::F32LE
+            } else {
+                Encoding::F32BE
+            };
+            let mut pixels = frame.pixels.clone();
+            for px in pixels.chunks_exact_mut(4) {
+                px[..3].iter_mut().for_each(|c| *c /= scale.abs())
+            }
+            pixels
+                .chunks_exact(frame.width * 4)
+                .rev()
+                .for_each(|row| write_samples(row, *channels, encoding, &mut out))
+        }
+    }
+    w.write_all(&out)?;
+    w.flush()
+}
+
+/// Processes frames from stdin to stdout until the stream ends
+pub fn run(ops: &[Operation], args: &Args) -> Result<(), String> {
+    let mut input = io::stdin().lock();
+    let mut output = BufWriter::new(io::stdout().lock());
+
+    for n in 0.. {
+        let mut frame = match read_frame(&mut input, args.raw) {
+            Ok(Some(f)) => f,
+            Ok(None) => break,
+            Err(e) => return Err(format!("Could not read frame {}: {}", n, e)),
+        };
+
+        apply(
+            ops,
+            args,
+            &mut frame.pixels,
+            frame.width,
+            &Params {
+                externals: Some(args.externals),
+                seed: args.seed,
+                jump_limit: args.jump_limit,
+                jump_limit_error: args.jump_limit_error,
+                lock_alpha: args.lock_alpha,
+                frame: n,
+                time: n as f32 / args.fps,
+                ..Default::default()
+            },
+            &format!("frame {}", n),
+        )?;
+
+        match write_frame(&mut output, &frame) {
+            Ok(()) => (),
+            // downstream closed, eg `| head`
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(format!("Could not write frame {}: {}", n, e)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8], raw: Option<(usize, usize, RawFormat)>) -> io::Result<Option<Frame>> {
+        read_frame(&mut io::Cursor::new(bytes), raw)
+    }
+
+    /// Reads a single frame and writes it back
+    fn round_trip(bytes: &[u8], raw: Option<(usize, usize, RawFormat)>) -> Frame {
+        let mut r = io::Cursor::new(bytes);
+        let frame = read_frame(&mut r, raw).unwrap().unwrap();
+        assert!(read_frame(&mut r, raw).unwrap().is_none());
+        let mut out = Vec::new();
+        write_frame(&mut out, &frame).unwrap();
+        assert_eq!(out, bytes);
+        frame
+    }
+
+    #[test]
+    fn ppm() {
+        let frame = round_trip(b"P6\n2 1\n255\n\xff\x00\x33\x00\x00\x00", None);
+        assert_eq!((frame.width, frame.height), (2, 1));
+        assert_eq!(frame.format, Format::Ppm(255));
+        assert_eq!(frame.pixels, [1.0, 0.0, 0.2, 1.0, 0.0, 0.0, 0.0, 1.0]);
+
+        // comments, and 16 bit big endian
+        let frame = read(
+            b"P6 # size\n1 1 # next\n65535\n\xff\xff\x00\x00\x80\x00",
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(frame.pixels[..2], [1.0, 0.0]);
+        assert!((frame.pixels[2] - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn pam() {
+        let header =
+            "P7\nWIDTH 1\nHEIGHT 2\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n";
+        let bytes = [header.as_bytes(), b"\xff\x00\x00\xff"].concat();
+        let frame = round_trip(&bytes, None);
+        assert_eq!(
+            frame.format,
+            Format::Pam {
+                depth: 2,
+                maxval: 255,
+                tupltype: "GRAYSCALE_ALPHA".to_string()
+            }
+        );
+        assert_eq!(frame.pixels, [1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+
+        let bad = read(
+            b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 5\nMAXVAL 255\nENDHDR\n",
+            None,
+        );
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn pfm() {
+        // negative scale is little endian, rows go bottom to top
+        let mut bytes = b"Pf\n1 2\n-1\n".to_vec();
+        bytes.extend(0.25f32.to_le_bytes());
+        bytes.extend(0.75f32.to_le_bytes());
+        let frame = round_trip(&bytes, None);
+        assert_eq!(frame.pixels, [0.75, 0.75, 0.75, 1.0, 0.25, 0.25, 0.25, 1.0]);
+
+        // positive is big endian, and the size scales the samples
+        let mut bytes = b"PF\n1 1\n2\n".to_vec();
+        for c in [0.25f32, 0.5, 1.0] {
+            bytes.extend(c.to_be_bytes());
+        }
+        let frame = round_trip(&bytes, None);
+        assert_eq!(frame.pixels, [0.5, 1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn raw() {
+        let raw = Some((2, 1, RawFormat::Rgba));
+        let frame = round_trip(b"\xff\x00\x00\xff\x00\x00\xff\x00", raw);
+        assert_eq!(frame.pixels, [1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0]);
+
+        let raw = Some((1, 1, RawFormat::Rgb48));
+        let frame = round_trip(b"\xff\xff\x00\x00\xff\xff", raw);
+        assert_eq!(frame.pixels, [1.0, 0.0, 1.0, 1.0]);
+
+        // a partial frame is an error, not the end
+        assert!(read(b"\xff\x00", Some((2, 1, RawFormat::Rgba))).is_err());
+        assert!(read(b"", Some((2, 1, RawFormat::Rgba))).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_headers() {
+        for header in [
+            &b"P6\n0 1\n255\n"[..],
+            b"P6\n1 1\n0\n",
+            b"P6\n99999999 99999999\n255\n",
+            b"P6\n1\n",
+            b"PF\n1 1\n0\n",
+            b"PF\n0 1\n-1\n",
+            b"P7\nWIDTH 0\nHEIGHT 1\nDEPTH 3\nENDHDR\n",
+            b"P3\n1 1\n255\n",
+        ] {
+            assert!(
+                read(header, None).is_err(),
+                "{}",
+                String::from_utf8_lossy(header)
+            );
+        }
+    }
+}
@@ -1,16 +1,25 @@
 use std::os::raw::c_char;
 
 pub mod pbcore;
-pub use pbcore::{parse_ops, process, Operation, Space};
+pub use pbcore::{parse_ops, process, process_with, trace, Operation, Params, Space};
 
 pub const HELP: &str = "\
 Valid lines:
     * {target} {operation} {source}
+    * {vector} {operation} {vector}
     * {space}
     * if {source} {comparison} {source} {line}
     * :{label}
     * jmp {label} or goto {label}
     * swap {target} {target}
+    * {target} {noise} {x} {y} [seed]
+    * gamut {mapping}
+    * adapt {white} {white} [model]
+    * temperature {source} or tint {source}
+    * whitepoint_from {source} {source} {source}
+    * blend {mode} [opacity]
+    * curve {name} [interpolation] {x,y} {x,y}...
+    * gradient {name} {position,colour} {position,colour}...
 
 Quick Example:
     r ** 2
@@ -31,7 +40,7 @@ Source:
     * any constant numeric value, eg '3.14'
     * 'pi'
     * 'e'
-    * 'rand' - random val between 0.0 -> 1.0
+    * 'rand' - random val between 0.0 -> 1.0, repeatable for the same seed
     * 'col' - pixel X
     * 'row' - pixel Y
     * 'width' - width of image
@@ -39,6 +48,21 @@ Source:
     * 'xnorm' - pixel X on scale of 0.0 -> 1.0
     * 'ynorm' - pixel Y on scale of 0.0 -> 1.0
     * 'hk2023' - Helmholtz-Kohlrausch factor for lightnes/chroma/hue spaces
+    * 'mask' - selection mask value for the pixel, 1.0 if there's no mask
+    * 'frame' - index of the current frame in a sequence or stream, 0 for single images
+    * 'time' - seconds since the first frame
+    * 'frames' - total number of frames, 0 if unknown
+    * 'ingamut' - 1.0 if the pixel can be shown in sRGB, else 0.0
+    * colour literals - '#ff8800', 'rgb(1, 0.5, 0)', or CSS names like 'tomato'.
+      sRGB, converted to the current space. Only for channel targets, which read the matching channel.
+      Eg: 'oklch; h = tomato' sets the hue to tomato's
+    * 'deltae76({colour})' - CIELAB distance from the pixel to a colour literal, or three variables
+      like 'v1v2v3' holding a colour in the current space. 1.0 is about the smallest visible difference
+    * 'deltae2000({colour})' - CIEDE2000, like 'deltae76' but closer to how differences are seen
+    * 'deltaok({colour})' - Oklab distance. Smaller scale, about 0.02 is visible
+      Eg: 'if deltae2000(#3a7bd5) < 10 v1 = 1' to select pixels near a blue
+    * 'l2.{channel}' - channel of the second layer in the current space, like 'l2.r' or 'l2.c4'.
+      Transparent black if there's no second layer. 'l2.rgb' also works as a vector
 
 Operation:
     Operations that take 2 values will source from target and source in order
@@ -56,6 +80,85 @@ Operation:
     * max min radians recip remeuclid round signum sin sinh sqrt tan tanh trunc
     * invert - a invert b == a = b - a
 
+Vectors:
+    Three channels or variables at once, like 'rgb', 'lch', 'c123', or 'v1v2v3'.
+    Channel letters must all belong to the current space.
+    Vector sources can also be colour literals, or any source to use for all three
+
+    * '{vector} {operation} {vector}' - any operation, channel by channel. Eg: 'rgb * 0.5'
+    * '{vector} mix {vector} {amount}' - moves toward the source. Eg: 'rgb mix #ff0000 0.3'
+    * '{vector} cross {vector}' - cross product
+    * '{target} dot {vector} {vector}' - dot product into a single target. Eg: 'v1 dot rgb v4v5v6'
+    * '{target} length {vector}' - length of a vector
+
+Blend:
+    'blend {mode} [opacity]' puts the second layer over the pixel like an image editor would,
+    using the layer's alpha times [opacity], default 1.0.
+    Eg: 'blend softlight 0.5' or 'oklab; blend multiply' to multiply in Oklab
+
+    * separable - 'normal' 'multiply' 'screen' 'overlay' 'softlight' 'hardlight' 'darken' 'lighten'
+      'difference' 'exclusion' 'add' 'subtract' 'colordodge' 'colorburn'.
+      Channel by channel in the current space, so usually wanted in sRGB
+    * 'hue' 'saturation' 'color' 'luminosity' - take Oklch hue, chroma, both, or lightness from the layer
+
+Curves and gradient maps:
+    Declared once, then looked up from any line below like a function of one source.
+    Both cover 0.0 -> 1.0 and stay level past the ends
+
+    * 'curve {name} [interpolation] {x,y}...' - a tone curve through points with increasing x.
+      Interpolation is 'cubic' (default), smooth without overshooting, or 'linear'.
+      Eg: 'curve tone 0,0 0.25,0.2 0.75,0.85 1,1' then 'oklch; l = tone(l)'
+    * 'gradient {name} {position,colour}...' - colour stops blended in Oklab.
+      Stops at the same position make a hard edge.
+      Eg: 'gradient heat 0,black 0.5,#e03000 1,#ffe090' then 'oklch; v1 = l; srgb; rgb = heat(v1)'
+    * '{target} {operation} {name}({source})' - looks up a curve, like 'v1 = tone(l)'
+    * '{vector} {operation} {name}({source})' - looks up a gradient into the current space
+
+    Points and stops are written without spaces
+
+Noise:
+    Deterministic 2D noise sampled at {x}, {y}. Scale the coordinates to change frequency.
+    Eg: 'v1 = xnorm; v1 * 8; v2 = ynorm; v2 * 8; r perlin v1 v2'
+
+    * 'value' - smoothed random lattice, 0.0 -> 1.0
+    * 'perlin' - gradient noise, 0.0 -> 1.0
+    * 'simplex' - gradient noise with fewer grid artifacts, 0.0 -> 1.0
+    * 'worley' - distance to nearest cell point, 0.0 -> ~1.0
+    * 'fbm' - layered perlin, takes an extra {octaves} source before [seed]
+
+    [seed] is any source, default 0. Combined with the processing seed like 'rand'
+
+Gamut:
+    Brings colours sRGB can't show back inside it. The pixel stays in the current space.
+
+    * 'clip' - clamps each sRGB channel. Fast but shifts hue
+    * 'chroma' - lowers Oklch chroma, keeping lightness and hue
+    * 'project' - moves toward middle grey, keeping hue and more chroma than 'chroma'
+
+RGB spaces:
+    Besides colcon's spaces, these switch to other RGB working spaces with channels 'r' 'g' 'b'
+
+    * 'displayp3' or 'p3' - Display P3, sRGB's curve with wider primaries
+    * 'rec2020' or 'bt2020' - ITU-R BT.2020 primaries and transfer
+    * 'adobergb' - Adobe RGB (1998)
+    * 'acescg' - ACES AP1 primaries, linear
+
+Adaptation:
+    'adapt {white} {white} [model]' shifts the pixel from looking right under the first white
+    to looking the same under the second, staying in the current space.
+    Eg: 'adapt d50 d65' for colours measured under print lighting
+
+    * whites - 'a' 'd50' 'd55' 'd60' 'd65' 'd75' 'e'
+    * models - 'bradford' (default) or 'cat16'
+
+White balance:
+    Corrects for the colour of the light in linear XYZ, staying in the current space.
+
+    * 'temperature {kelvin}' - undoes a light of that temperature. Lower is bluer, 6504 does nothing
+    * 'tint {amount}' - undoes a light that far toward green. Positive adds magenta, around -1.0 -> 1.0
+    * 'whitepoint_from {r} {g} {b}' - makes the sRGB colour neutral, like a picked grey.
+      Eg: 'whitepoint_from e1 e2 e3' with the colour passed as externals
+
 Comparison:
     * '==' or 'eq'
     * '!=' or '!' or 'neq'
@@ -69,12 +172,15 @@ Notes:
     Lines ending with '\\' are continued to next
     ';' counts as a linebreak anywhere in code
 
-    There's a hard limit of 99 jumps per pixel as it can't detect loops
+    Each pixel may jump 100 times before it's stopped where it is, as loops can't be detected.
+    Programs using pixelbuster can change the limit
 
     v1 through v9 start at 0.0 every pixel
 
     e1 through e9 are 'external variables' that can be assigned starting values
-    Useful for creating things like UI control sliders";
+    Useful for creating things like UI control sliders
+
+    If a mask is provided, each result is blended with the original pixel by the mask value";
 
 pub fn pixelbuster<S: AsRef<str>>(
     code: S,
@@ -113,6 +219,7 @@ pub extern "C" fn pixelbuster_ffi(
 }
 
 #[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn pixelbuster_ffi_ext(
     code: *const c_char,
     channels: *const c_char,
@@ -128,6 +235,138 @@ pub extern "C" fn pixelbuster_ffi_ext(
     e7: f32,
     e8: f32,
     e9: f32,
+) {
+    pixelbuster_ffi_mask(
+        code,
+        channels,
+        pixels,
+        pixels_size,
+        width,
+        std::ptr::null(),
+        0,
+        e1,
+        e2,
+        e3,
+        e4,
+        e5,
+        e6,
+        e7,
+        e8,
+        e9,
+    )
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn pixelbuster_ffi_mask(
+    code: *const c_char,
+    channels: *const c_char,
+    pixels: *mut u8,
+    pixels_size: usize,
+    width: usize,
+    mask: *const u8,
+    mask_size: usize,
+    e1: f32,
+    e2: f32,
+    e3: f32,
+    e4: f32,
+    e5: f32,
+    e6: f32,
+    e7: f32,
+    e8: f32,
+    e9: f32,
+) {
+    pixelbuster_ffi_layer(
+        code,
+        channels,
+        pixels,
+        pixels_size,
+        width,
+        std::ptr::null(),
+        0,
+        mask,
+        mask_size,
+        e1,
+        e2,
+        e3,
+        e4,
+        e5,
+        e6,
+        e7,
+        e8,
+        e9,
+    )
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn pixelbuster_ffi_layer(
+    code: *const c_char,
+    channels: *const c_char,
+    pixels: *mut u8,
+    pixels_size: usize,
+    width: usize,
+    layer: *const u8,
+    layer_size: usize,
+    mask: *const u8,
+    mask_size: usize,
+    e1: f32,
+    e2: f32,
+    e3: f32,
+    e4: f32,
+    e5: f32,
+    e6: f32,
+    e7: f32,
+    e8: f32,
+    e9: f32,
+) {
+    pixelbuster_ffi_alpha(
+        code,
+        channels,
+        pixels,
+        pixels_size,
+        width,
+        layer,
+        layer_size,
+        mask,
+        mask_size,
+        false,
+        false,
+        e1,
+        e2,
+        e3,
+        e4,
+        e5,
+        e6,
+        e7,
+        e8,
+        e9,
+    )
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn pixelbuster_ffi_alpha(
+    code: *const c_char,
+    channels: *const c_char,
+    pixels: *mut u8,
+    pixels_size: usize,
+    width: usize,
+    layer: *const u8,
+    layer_size: usize,
+    mask: *const u8,
+    mask_size: usize,
+    premultiplied: bool,
+    lock_alpha: bool,
+    e1: f32,
+    e2: f32,
+    e3: f32,
+    e4: f32,
+    e5: f32,
+    e6: f32,
+    e7: f32,
+    e8: f32,
+    e9: f32,
 ) {
     let code = unsafe {
         assert!(!code.is_null());
@@ -147,13 +386,33 @@ pub extern "C" fn pixelbuster_ffi_ext(
         assert!(!pixels.is_null());
         std::slice::from_raw_parts_mut(pixels.cast::<f32>(), pixels_size / 4)
     };
+    let layer = if layer.is_null() {
+        None
+    } else {
+        Some(unsafe { std::slice::from_raw_parts(layer.cast::<f32>(), layer_size / 4) })
+    };
+    let mask = if mask.is_null() {
+        None
+    } else {
+        Some(unsafe { std::slice::from_raw_parts(mask.cast::<f32>(), mask_size / 4) })
+    };
 
-    pixelbuster(
-        &code,
-        Space::try_from(channels.as_str()).unwrap_or(Space::SRGB),
+    process_with(
+        parse_ops(
+            code,
+            Space::try_from(channels.as_str()).unwrap_or(Space::SRGB),
+        )
+        .0,
         pixels,
         width,
-        Some([e1, e2, e3, e4, e5, e6, e7, e8, e9]),
+        &Params {
+            externals: Some([e1, e2, e3, e4, e5, e6, e7, e8, e9]),
+            mask,
+            layer,
+            premultiplied,
+            lock_alpha,
+            ..Default::default()
+        },
     );
 }
 
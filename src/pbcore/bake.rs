@@ -0,0 +1,163 @@
+//! Baking programs into 3D lookup tables other tools can apply.
+
+use colcon::{convert_space_sliced, Space};
+
+use super::{parse::accesses, process_with, Obj, Operation, Params};
+
+/// A program evaluated over a lattice of colours
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3D {
+    /// Points along each axis
+    pub size: usize,
+    /// Result at every point, red changing fastest then green then blue
+    pub table: Vec<[f32; 3]>,
+}
+
+/// Why a program couldn't be baked
+#[derive(Clone, Debug, PartialEq)]
+pub enum BakeError {
+    /// The operation at this index reads something besides the pixel's colour,
+    /// like its position, `rand`, the frame, or the second layer, so a table can't hold it.
+    /// The operation is as its `Display`
+    NotPerPixel { index: usize, operation: String },
+    /// Lattices need at least 2 points per axis
+    Size(usize),
+}
+
+impl std::fmt::Display for BakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BakeError::NotPerPixel { operation, .. } => write!(
+                f,
+                "'{}' depends on more than the pixel's colour, so it can't be baked",
+                operation
+            ),
+            BakeError::Size(size) => {
+                write!(f, "Can't bake {} points per axis, need at least 2", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BakeError {}
+
+/// Whether `operation` only depends on the colour it's given
+fn per_pixel(operation: &Operation) -> bool {
+    match operation {
+        Operation::Blend { .. } => false,
+        Operation::If { then, .. } if !per_pixel(then) => false,
+        _ => accesses(operation).0.into_iter().all(|o| {
+            !matches!(
+                o,
+                Obj::Rand
+                    | Obj::Row
+                    | Obj::Col
+                    | Obj::Width
+                    | Obj::Height
+                    | Obj::XNorm
+                    | Obj::YNorm
+                    | Obj::Mask
+                    | Obj::Frame
+                    | Obj::Time
+                    | Obj::Frames
+                    | Obj::Layer(_)
+            )
+        }),
+    }
+}
+
+/// Runs `ops` on every point of a `size`×`size`×`size` lattice spanning 0.0 -> 1.0 in `space`,
+/// converting to the space the program starts in and back. Alpha is always 1.0.
+///
+/// `Params` are used as in `process_with()`, except the mask, layer, and region of interest.
+pub fn bake<O: AsRef<[Operation]>>(
+    ops: O,
+    size: usize,
+    space: Space,
+    params: &Params,
+) -> Result<Lut3D, BakeError> {
+    let ops: &[Operation] = ops.as_ref();
+    if size < 2 {
+        return Err(BakeError::Size(size));
+    }
+    if let Some((index, operation)) = ops.iter().enumerate().find(|(_, o)| !per_pixel(o)) {
+        return Err(BakeError::NotPerPixel {
+            index,
+            operation: operation.to_string(),
+        });
+    }
+
+    let step = (size - 1) as f32;
+    let mut pixels: Vec<f32> = (0..size * size * size)
+        .flat_map(|i| {
+            [
+                (i % size) as f32 / step,
+                (i / size % size) as f32 / step,
+                (i / size / size) as f32 / step,
+                1.0,
+            ]
+        })
+        .collect();
+    let start = match ops.first() {
+        Some(Operation::Space(s)) => *s,
+        _ => space,
+    };
+    convert_space_sliced::<f32, 4>(space, start, &mut pixels);
+    process_with(
+        ops,
+        &mut pixels,
+        size,
+        &Params {
+            mask: None,
+            layer: None,
+            roi: None,
+            ..*params
+        },
+    );
+    convert_space_sliced::<f32, 4>(start, space, &mut pixels);
+
+    Ok(Lut3D {
+        size,
+        table: pixels.chunks_exact(4).map(|p| [p[0], p[1], p[2]]).collect(),
+    })
+}
+
+impl Lut3D {
+    /// The table as an Adobe/Resolve `.cube` file
+    pub fn to_cube(&self) -> String {
+        let mut cube = format!("# Baked by pixelbuster\nLUT_3D_SIZE {}\n", self.size);
+        for [r, g, b] in &self.table {
+            cube += &format!("{:.6} {:.6} {:.6}\n", r, g, b);
+        }
+        cube
+    }
+
+    /// The table as an Autodesk `.3dl` file, with 10 bit input and 12 bit output.
+    /// Values outside 0.0 -> 1.0 are clipped, as the format is integers
+    pub fn to_3dl(&self) -> String {
+        let n = self.size;
+        let mesh: Vec<String> = (0..n)
+            .map(|i| ((i * 1023) as f32 / (n - 1) as f32).round().to_string())
+            .collect();
+        let mut lut = mesh.join(" ") + "\n";
+        // blue changes fastest
+        for r in 0..n {
+            for g in 0..n {
+                for b in 0..n {
+                    let c = self.table[r + g * n + b * n * n]
+                        .map(|c| (c.clamp(0.0, 1.0) * 4095.0).round() as u16);
+                    lut += &format!("{} {} {}\n", c[0], c[1], c[2]);
+                }
+            }
+        }
+        lut
+    }
+
+    /// Level of the Hald CLUT holding this table, if `size` is a square.
+    /// The image is `size * level` pixels wide and tall with the table in reading order
+    pub fn hald_level(&self) -> Option<usize> {
+        (1..=self.size)
+            .take_while(|l| l * l <= self.size)
+            .find(|l| l * l == self.size)
+    }
+}
@@ -0,0 +1,102 @@
+//! Layer blend modes, following the W3C compositing spec.
+//!
+//! Separable modes work channel by channel in whatever space the pixel is in,
+//! so they behave like image editors' in sRGB. The others swap lightness, chroma, and hue in Oklch.
+
+use colcon::Space;
+
+use super::{Blend, PixelState};
+
+/// `base` blended with `top` by a separable mode. Channels are expected to be around 0.0 -> 1.0
+pub fn separable(mode: Blend, base: f32, top: f32) -> f32 {
+    match mode {
+        Blend::Normal => top,
+        Blend::Multiply => base * top,
+        Blend::Screen => base + top - base * top,
+        Blend::Overlay => separable(Blend::HardLight, top, base),
+        Blend::SoftLight => {
+            if top <= 0.5 {
+                base - (1.0 - 2.0 * top) * base * (1.0 - base)
+            } else {
+                let d = if base <= 0.25 {
+                    ((16.0 * base - 12.0) * base + 4.0) * base
+                } else {
+                    base.sqrt()
+                };
+                base + (2.0 * top - 1.0) * (d - base)
+            }
+        }
+        Blend::HardLight => {
+            if top <= 0.5 {
+                base * 2.0 * top
+            } else {
+                separable(Blend::Screen, base, 2.0 * top - 1.0)
+            }
+        }
+        Blend::Darken => base.min(top),
+        Blend::Lighten => base.max(top),
+        Blend::Difference => (base - top).abs(),
+        Blend::Exclusion => base + top - 2.0 * base * top,
+        Blend::Add => base + top,
+        Blend::Subtract => base - top,
+        Blend::ColorDodge => {
+            if base <= 0.0 {
+                0.0
+            } else if top >= 1.0 {
+                1.0
+            } else {
+                (base / (1.0 - top)).min(1.0)
+            }
+        }
+        Blend::ColorBurn => {
+            if base >= 1.0 {
+                1.0
+            } else if top <= 0.0 {
+                0.0
+            } else {
+                1.0 - ((1.0 - base) / top).min(1.0)
+            }
+        }
+        // not separable, these keep the base
+        Blend::Hue | Blend::Saturation | Blend::Color | Blend::Luminosity => base,
+    }
+}
+
+/// Blends `top` over `pixel`, both in the current space of `state`.
+///
+/// The blended colour is mixed in by `top`'s alpha times `opacity`, and alphas combine like
+/// stacked layers. Where `pixel` is transparent the result leans toward plain `top`.
+pub fn blend(mode: Blend, pixel: &mut [f32; 4], top: [f32; 4], opacity: f32, state: &PixelState) {
+    let base = [pixel[0], pixel[1], pixel[2]];
+    let color = [top[0], top[1], top[2]];
+    let mut blended = match mode {
+        Blend::Hue | Blend::Saturation | Blend::Color | Blend::Luminosity => {
+            let (mut b, mut t) = (base, color);
+            state.convert_color(&mut b, Space::OKLCH);
+            state.convert_color(&mut t, Space::OKLCH);
+            let mut lch = match mode {
+                Blend::Hue => [b[0], b[1], t[2]],
+                Blend::Saturation => [b[0], t[1], b[2]],
+                Blend::Color => [b[0], t[1], t[2]],
+                _ => [t[0], b[1], b[2]],
+            };
+            state.restore_color(&mut lch, Space::OKLCH);
+            lch
+        }
+        _ => [0, 1, 2].map(|i| separable(mode, base[i], color[i])),
+    };
+
+    let (alpha, k) = (pixel[3], top[3] * opacity);
+    for (b, c) in blended.iter_mut().zip(color) {
+        *b = c + (*b - c) * alpha
+    }
+    let out = k + alpha * (1.0 - k);
+    if out == 0.0 {
+        pixel[3] = 0.0;
+        return;
+    }
+    for ((p, b), o) in pixel.iter_mut().zip(blended).zip(base) {
+        *p = (k * b + alpha * (1.0 - k) * o) / out
+    }
+    pixel[3] = out;
+}
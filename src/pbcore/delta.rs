@@ -0,0 +1,95 @@
+//! Perceptual colour differences.
+
+use colcon::Space;
+
+/// Colour difference formulas
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Delta {
+    /// Euclidean distance in CIELAB. 1.0 is about a just noticeable difference
+    E76,
+    /// CIEDE2000, CIELAB corrected for how hue and chroma are actually seen
+    E2000,
+    /// Euclidean distance in Oklab, where lightness runs 0.0 -> 1.0
+    Ok,
+}
+
+impl Delta {
+    /// Space colours are compared in
+    pub fn space(self) -> Space {
+        match self {
+            Delta::E76 | Delta::E2000 => Space::CIELAB,
+            Delta::Ok => Space::OKLAB,
+        }
+    }
+
+    /// Difference between two colours, both in `self.space()`
+    pub fn distance(self, a: [f32; 3], b: [f32; 3]) -> f32 {
+        match self {
+            Delta::E76 | Delta::Ok => {
+                ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+            }
+            Delta::E2000 => e2000(a, b),
+        }
+    }
+}
+
+/// CIEDE2000 with unit weights, following Sharma, Wu, and Dalal's notes
+fn e2000(lab1: [f32; 3], lab2: [f32; 3]) -> f32 {
+    let [l1, a1, b1] = lab1.map(|c| c as f64);
+    let [l2, a2, b2] = lab2.map(|c| c as f64);
+    let pow7 = |x: f64| x.powi(7);
+
+    let c_mean = (a1.hypot(b1) + a2.hypot(b2)) / 2.0;
+    let g = 0.5 * (1.0 - (pow7(c_mean) / (pow7(c_mean) + pow7(25.0))).sqrt());
+    let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |a: f64, b: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (pow7(c_mean) / (pow7(c_mean) + pow7(25.0))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (dl / s_l, dc / s_c, dh / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt() as f32
+}
@@ -0,0 +1,83 @@
+//! Bringing colours outside of sRGB back inside it.
+//!
+//! All functions take a pixel in `space` and leave it in `space`.
+
+use colcon::{convert_space, Space};
+
+/// Slack for float error at the edges of the gamut
+const EPSILON: f32 = 1e-4;
+
+/// Bisection steps. Enough to land well within `EPSILON`
+const STEPS: usize = 24;
+
+fn inside(lrgb: &[f32; 3]) -> bool {
+    lrgb.iter().all(|c| (-EPSILON..=1.0 + EPSILON).contains(c))
+}
+
+/// Whether `pixel` is displayable in sRGB
+pub fn in_gamut(pixel: &[f32; 3], space: Space) -> bool {
+    let mut lrgb = *pixel;
+    convert_space(space, Space::LRGB, &mut lrgb);
+    inside(&lrgb)
+}
+
+/// Clamps each sRGB channel. Fast, but shifts hue and lightness on strong colours.
+pub fn clip(pixel: &mut [f32; 3], space: Space) {
+    convert_space(space, Space::SRGB, pixel);
+    pixel.iter_mut().for_each(|c| *c = c.clamp(0.0, 1.0));
+    convert_space(Space::SRGB, space, pixel);
+}
+
+/// Moves the Oklab colour `pixel` toward `anchor` until it's just inside sRGB
+fn toward(pixel: &mut [f32; 3], anchor: [f32; 3]) {
+    let at = |t: f32| -> [f32; 3] {
+        [
+            anchor[0] + (pixel[0] - anchor[0]) * t,
+            anchor[1] + (pixel[1] - anchor[1]) * t,
+            anchor[2] + (pixel[2] - anchor[2]) * t,
+        ]
+    };
+    let fits = |t: f32| {
+        let mut lrgb = at(t);
+        convert_space(Space::OKLAB, Space::LRGB, &mut lrgb);
+        inside(&lrgb)
+    };
+    if fits(1.0) {
+        return;
+    }
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..STEPS {
+        let mid = (lo + hi) / 2.0;
+        if fits(mid) {
+            lo = mid
+        } else {
+            hi = mid
+        }
+    }
+    *pixel = at(lo);
+}
+
+/// Lowers Oklch chroma until the colour fits, keeping lightness and hue.
+/// Lightness outside 0 -> 1 becomes black or white.
+pub fn chroma(pixel: &mut [f32; 3], space: Space) {
+    convert_space(space, Space::OKLAB, pixel);
+    pixel[0] = pixel[0].clamp(0.0, 1.0);
+    toward(pixel, [pixel[0], 0.0, 0.0]);
+    clip_residue(pixel, space);
+}
+
+/// Moves the colour in a straight line toward middle grey until it fits, keeping hue.
+/// Trades some lightness to keep more chroma than `chroma()` for bright and dark colours.
+pub fn project(pixel: &mut [f32; 3], space: Space) {
+    convert_space(space, Space::OKLAB, pixel);
+    toward(pixel, [0.5, 0.0, 0.0]);
+    clip_residue(pixel, space);
+}
+
+/// Takes an Oklab pixel that's within `EPSILON` of sRGB back to `space`,
+/// clamping away the leftover float error
+fn clip_residue(pixel: &mut [f32; 3], space: Space) {
+    convert_space(Space::OKLAB, Space::SRGB, pixel);
+    pixel.iter_mut().for_each(|c| *c = c.clamp(0.0, 1.0));
+    convert_space(Space::SRGB, space, pixel);
+}
@@ -0,0 +1,156 @@
+//! Lookup tables for `curve` and `gradient` declarations.
+//!
+//! Both are sampled over 0.0 -> 1.0 when declared, so a lookup is two reads and a lerp.
+
+use colcon::{convert_space, Space};
+
+/// Entries in every table
+pub const SIZE: usize = 1024;
+
+/// How a curve gets from one point to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Interp {
+    /// Straight lines
+    Linear,
+    /// Monotone cubic. Smooth, and never overshoots between points
+    Cubic,
+}
+
+/// A tone curve through points with increasing x
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Curve {
+    pub name: String,
+    pub interp: Interp,
+    pub points: Vec<[f32; 2]>,
+    /// `SIZE` samples of the curve
+    pub table: Vec<f32>,
+}
+
+/// A gradient map through sRGB colour stops, blended in Oklab
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Gradient {
+    pub name: String,
+    /// Positions, which never decrease, and sRGB colours
+    pub stops: Vec<(f32, [f32; 3])>,
+    /// `SIZE` samples of the gradient, in the space it's used in once parsed
+    pub table: Vec<[f32; 3]>,
+}
+
+/// Index of the sample at or before `x` and how far it is toward the next.
+/// `x` is clamped to 0.0 -> 1.0
+#[inline]
+fn locate(x: f32) -> (usize, f32) {
+    let pos = x.clamp(0.0, 1.0) * (SIZE - 1) as f32;
+    let i = (pos as usize).min(SIZE - 2);
+    (i, pos - i as f32)
+}
+
+/// Index of the segment of `xs` holding `x`, and how far along it `x` is
+fn segment(xs: impl ExactSizeIterator<Item = f32> + Clone, x: f32) -> (usize, f32) {
+    let last = xs.len() - 1;
+    let i = xs
+        .clone()
+        .skip(1)
+        .position(|p| x < p)
+        .unwrap_or(last)
+        .min(last - 1);
+    let (x0, x1) = (xs.clone().nth(i).unwrap(), xs.clone().nth(i + 1).unwrap());
+    let t = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+    (i, t.clamp(0.0, 1.0))
+}
+
+impl Curve {
+    /// Samples a curve through at least two `points` with increasing x.
+    /// Beyond the first and last points it stays level
+    pub fn new(name: String, interp: Interp, points: Vec<[f32; 2]>) -> Self {
+        let n = points.len();
+        let h: Vec<f32> = points.windows(2).map(|p| p[1][0] - p[0][0]).collect();
+        let d: Vec<f32> = points
+            .windows(2)
+            .zip(&h)
+            .map(|(p, h)| (p[1][1] - p[0][1]) / h)
+            .collect();
+        // Fritsch-Carlson tangents, Brodlie's weighted harmonic mean of the neighbouring slopes,
+        // which keep each segment monotone
+        let mut m = vec![0.0; n];
+        m[0] = d[0];
+        m[n - 1] = d[n - 2];
+        for k in 1..n - 1 {
+            if d[k - 1] * d[k] > 0.0 {
+                let (w1, w2) = (2.0 * h[k] + h[k - 1], h[k] + 2.0 * h[k - 1]);
+                m[k] = (w1 + w2) / (w1 / d[k - 1] + w2 / d[k]);
+            }
+        }
+
+        let table = (0..SIZE)
+            .map(|s| {
+                let x = s as f32 / (SIZE - 1) as f32;
+                let (k, t) = segment(points.iter().map(|p| p[0]), x);
+                let (y0, y1) = (points[k][1], points[k + 1][1]);
+                match interp {
+                    Interp::Linear => y0 + (y1 - y0) * t,
+                    Interp::Cubic => {
+                        let (t2, t3) = (t * t, t * t * t);
+                        (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+                            + (t3 - 2.0 * t2 + t) * h[k] * m[k]
+                            + (-2.0 * t3 + 3.0 * t2) * y1
+                            + (t3 - t2) * h[k] * m[k + 1]
+                    }
+                }
+            })
+            .collect();
+        Self {
+            name,
+            interp,
+            points,
+            table,
+        }
+    }
+
+    /// The curve at `x`, from 0.0 -> 1.0
+    #[inline]
+    pub fn sample(&self, x: f32) -> f32 {
+        let (i, t) = locate(x);
+        let (a, b) = (self.table[i], self.table[i + 1]);
+        a + (b - a) * t
+    }
+}
+
+impl Gradient {
+    /// Samples a gradient through at least two `stops` with positions that never decrease.
+    /// Stops at the same position make a hard edge.
+    /// The table starts out sRGB
+    pub fn new(name: String, stops: Vec<(f32, [f32; 3])>) -> Self {
+        let oklab: Vec<[f32; 3]> = stops
+            .iter()
+            .map(|(_, c)| {
+                let mut c = *c;
+                convert_space(Space::SRGB, Space::OKLAB, &mut c);
+                c
+            })
+            .collect();
+        let table = (0..SIZE)
+            .map(|s| {
+                let x = s as f32 / (SIZE - 1) as f32;
+                let (k, t) = segment(stops.iter().map(|s| s.0), x);
+                let (a, b) = (oklab[k], oklab[k + 1]);
+                let mut c = [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t);
+                convert_space(Space::OKLAB, Space::SRGB, &mut c);
+                c
+            })
+            .collect();
+        Self { name, stops, table }
+    }
+
+    /// The gradient at `x`, from 0.0 -> 1.0
+    #[inline]
+    pub fn sample(&self, x: f32) -> [f32; 3] {
+        let (i, t) = locate(x);
+        let (a, b) = (self.table[i], self.table[i + 1]);
+        [0, 1, 2].map(|c| a[c] + (b[c] - a[c]) * t)
+    }
+}
@@ -1,247 +1,1050 @@
 use std::f32::consts::{E, PI};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::thread::{self, ScopedJoinHandle};
+use std::time::{Duration, Instant};
 
 use colcon::{convert_space, hk_high2023};
-use fastrand;
 
+pub mod bake;
+pub mod blend;
+pub mod color;
+pub mod delta;
+pub mod gamut;
+pub mod lut;
+pub mod noise;
 pub mod parse;
-pub use parse::{parse_ops, Cmp, Obj, Op, OpError, Operation};
+pub mod rgb;
+pub use bake::{bake, BakeError, Lut3D};
+pub use delta::Delta;
+pub use lut::{Curve, Gradient, Interp};
+pub use parse::{
+    format_code, parse_ops, parse_ops_ext, Blend, Cmp, Gamut, Lint, Noise, Obj, Op, OpError,
+    Operation, VecOp, Vector, Warning,
+};
+pub use rgb::{Cat, Illuminant, Rgb};
 
 pub use colcon::Space;
 
+/// Per-run inputs shared by every pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Params<'a> {
+    /// Starting values of e1 through e9
+    pub externals: Option<[f32; 9]>,
+    /// Seed for `rand`. The same seed always produces the same image regardless of threading.
+    pub seed: u64,
+    /// One value per pixel weighting the result against the original, like a selection.
+    /// 0.0 leaves the pixel untouched, 1.0 keeps the full result.
+    pub mask: Option<&'a [f32]>,
+    /// A second image the same size and layout as the pixels, in the space the program starts in,
+    /// for `l2` sources and `blend`. Reads as transparent black if there's none.
+    pub layer: Option<&'a [f32]>,
+    /// The pixels and layer have colour multiplied by alpha, as from most compositors.
+    /// Colour is divided out before the program and multiplied back after,
+    /// so scripts and space conversions see straight colour. Fully transparent pixels are read as is.
+    pub premultiplied: bool,
+    /// Put alpha back as it was after the program, so nothing a script does can change it.
+    /// Space conversions never touch alpha either way.
+    pub lock_alpha: bool,
+    /// Only process pixels inside this rectangle. Coordinate sources still report
+    /// positions in the full image. Requires a known width.
+    pub roi: Option<Rect>,
+    /// Index of the current frame for the `frame` source.
+    pub frame: usize,
+    /// Seconds into the animation for the `time` source.
+    pub time: f32,
+    /// Total frame count for the `frames` source. 0 if unknown.
+    pub frames: usize,
+    /// Jumps a pixel may take before it's stopped where it is. None for the default of 100.
+    pub jump_limit: Option<usize>,
+    /// Have `process_progress()` return an error if any pixel hit the jump limit.
+    pub jump_limit_error: bool,
+    /// Worker threads to split the image between. 0 for one per core.
+    /// Tiny images always use one.
+    pub threads: usize,
+}
+
+/// Default for `Params::jump_limit`
+pub const JUMP_LIMIT: usize = 100;
+
+/// A rectangle of pixels, from the top left.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Everything besides the pixel a program can change while running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelState {
+    /// v1 through v9 then e1 through e9
+    pub vars: [f32; 18],
+    pub space: Space,
+    /// RGB space the pixel is in when it isn't one colcon knows.
+    /// `space` is XYZ while this is set, as that's what it converts through.
+    pub rgb: Option<Rgb>,
+    /// Times `rand` has been read so far
+    pub rand_calls: u32,
+    /// The second layer's pixel in the current space, once it's been read
+    layer: Option<[f32; 4]>,
+}
+
+impl PixelState {
+    /// Fresh state with zeroed variables and externals from `params`
+    pub fn new(space: Space, params: &Params) -> Self {
+        let e = params.externals.unwrap_or([0.0_f32; 9]);
+        Self {
+            vars: [
+                0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, e[0], e[1], e[2], e[3], e[4], e[5],
+                e[6], e[7], e[8],
+            ],
+            space,
+            rgb: None,
+            rand_calls: 0,
+            layer: None,
+        }
+    }
+
+    /// Converts `pixel` from wherever it is to `space`
+    pub fn convert(&mut self, pixel: &mut [f32; 4], space: Space) {
+        if let Some(rgb) = self.rgb.take() {
+            rgb.to_xyz(pixel)
+        }
+        convert_space(self.space, space, pixel);
+        self.space = space;
+        self.layer = None;
+    }
+
+    /// Converts `pixel` from wherever it is to the custom RGB space `rgb`
+    pub fn convert_rgb(&mut self, pixel: &mut [f32; 4], rgb: Rgb) {
+        if self.rgb == Some(rgb) {
+            return;
+        }
+        self.convert(pixel, Space::XYZ);
+        rgb.from_xyz(pixel);
+        self.rgb = Some(rgb);
+    }
+
+    /// Converts `color` from the pixel's current space to `space`
+    #[inline]
+    fn convert_color(&self, color: &mut [f32; 3], space: Space) {
+        match self.rgb {
+            Some(rgb) => {
+                rgb.to_xyz(color);
+                convert_space(Space::XYZ, space, color)
+            }
+            None => convert_space(self.space, space, color),
+        }
+    }
+
+    /// Converts `color` from `space` to the pixel's current space
+    #[inline]
+    fn restore_color(&self, color: &mut [f32; 3], space: Space) {
+        match self.rgb {
+            Some(rgb) => {
+                convert_space(space, Space::XYZ, color);
+                rgb.from_xyz(color)
+            }
+            None => convert_space(space, self.space, color),
+        }
+    }
+
+    /// Runs `f` on the colour of `pixel` as XYZ, leaving it in the current space
+    #[inline]
+    fn in_xyz(&self, pixel: &mut [f32; 4], f: impl FnOnce(&mut [f32; 3])) {
+        let mut color = [pixel[0], pixel[1], pixel[2]];
+        self.convert_color(&mut color, Space::XYZ);
+        f(&mut color);
+        self.restore_color(&mut color, Space::XYZ);
+        pixel[..3].copy_from_slice(&color);
+    }
+
+    /// Whether the pixel is in `space` and not a custom RGB space
+    pub fn is_in(&self, space: Space) -> bool {
+        self.space == space && self.rgb.is_none()
+    }
+}
+
+/// Where a pixel sits and what it can see, for sources like `col` or `mask`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Env<'a> {
+    pub col: usize,
+    pub row: usize,
+    pub width: usize,
+    pub height: usize,
+    pub mask: f32,
+    pub params: &'a Params<'a>,
+}
+
+/// How control moved on after an operation ran.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    /// Whether an `if` ran its operation
+    If(bool),
+    /// A jump to the operation at this index
+    Jump(usize),
+    /// A jump that was skipped and ended the program, as it hit the loop limit
+    JumpLimit,
+}
+
+/// Applies `operation` to one channel or variable
+#[inline(always)]
+fn apply(operation: &Op, tar: &mut f32, src: f32) {
+    match operation {
+        // Base
+        Op::Add => *tar += src,
+        Op::Sub => *tar -= src,
+        Op::Mul => *tar *= src,
+        Op::Div => *tar /= src,
+        Op::Mod => *tar %= src,
+        Op::Pow => *tar = tar.powf(src),
+        Op::Set => *tar = src,
+        // Extended
+        Op::Abs => *tar = src.abs(),
+        Op::Acos => *tar = src.acos(),
+        Op::Acosh => *tar = src.acosh(),
+        Op::Asin => *tar = src.asin(),
+        Op::Asinh => *tar = src.asinh(),
+        Op::Atan => *tar = src.atan(),
+        Op::Atan2 => *tar = tar.atan2(src),
+        Op::Atanh => *tar = src.atanh(),
+        Op::Cbrt => *tar = src.cbrt(),
+        Op::Ceil => *tar = src.ceil(),
+        Op::Copysign => *tar = tar.copysign(src),
+        Op::Cos => *tar = src.cos(),
+        Op::Cosh => *tar = src.cosh(),
+        Op::Degrees => *tar = src.to_degrees(),
+        Op::Diveuclid => *tar = tar.div_euclid(src),
+        Op::Exp => *tar = src.exp(),
+        Op::Exp2 => *tar = src.exp2(),
+        Op::Expm1 => *tar = src.exp_m1(),
+        Op::Floor => *tar = src.floor(),
+        Op::Fract => *tar = src.fract(),
+        Op::Hypot => *tar = tar.hypot(src),
+        Op::Ln => *tar = src.ln(),
+        Op::Ln1p => *tar = src.ln_1p(),
+        Op::Log => *tar = tar.log(src),
+        Op::Log10 => *tar = src.log10(),
+        Op::Log2 => *tar = src.log2(),
+        Op::Max => *tar = tar.max(src),
+        Op::Min => *tar = tar.min(src),
+        Op::Radians => *tar = src.to_radians(),
+        Op::Recip => *tar = src.recip(),
+        Op::Remeuclid => *tar = tar.rem_euclid(src),
+        Op::Round => *tar = src.round(),
+        Op::Signum => *tar = src.signum(),
+        Op::Sin => *tar = src.sin(),
+        Op::Sinh => *tar = src.sinh(),
+        Op::Sqrt => *tar = src.sqrt(),
+        Op::Tan => *tar = src.tan(),
+        Op::Tanh => *tar = src.tanh(),
+        Op::Trunc => *tar = src.trunc(),
+        // Custom
+        Op::Invert => *tar = src - *tar,
+    }
+}
+
+/// Value of a `Delta` or `DeltaVars` source.
+/// Kept out of line so the interpreter loop stays small
+#[inline(never)]
+fn difference(obj: Obj, pixel: &[f32; 4], state: &PixelState) -> f32 {
+    let (delta, reference) = match obj {
+        Obj::Delta(delta, reference) => (delta, reference),
+        Obj::DeltaVars(delta, [a, b, c]) => {
+            let vars = &state.vars;
+            let mut reference = [vars[a as usize], vars[b as usize], vars[c as usize]];
+            state.convert_color(&mut reference, delta.space());
+            (delta, reference)
+        }
+        _ => panic!("This shouldn't be reachable"),
+    };
+    let mut color = [pixel[0], pixel[1], pixel[2]];
+    state.convert_color(&mut color, delta.space());
+    delta.distance(color, reference)
+}
+
+/// Divides colour by alpha, leaving fully transparent pixels as they are
+#[inline]
+fn unpremultiply(pixel: &mut [f32; 4]) {
+    let alpha = pixel[3];
+    if alpha != 0.0 {
+        pixel[..3].iter_mut().for_each(|c| *c /= alpha)
+    }
+}
+
+/// Undoes `unpremultiply()` and `Params::lock_alpha` once the program is done.
+/// Kept out of line so the pixel loop stays small
+#[inline(never)]
+fn restore_alpha(pixel: &mut [f32; 4], orig: &[f32; 4], params: &Params) {
+    if params.lock_alpha {
+        pixel[3] = orig[3]
+    }
+    if params.premultiplied {
+        let alpha = pixel[3];
+        pixel[..3].iter_mut().for_each(|c| *c *= alpha)
+    }
+}
+
+/// `Params::layer` under the pixel, converted from the space `ops` start in to the current one.
+/// Kept in `state` until the space changes, and out of line so the interpreter loop stays small
+#[inline(never)]
+fn layer(ops: &[Operation], env: &Env, state: &mut PixelState) -> [f32; 4] {
+    if let Some(layer) = state.layer {
+        return layer;
+    }
+    let index = env.col + env.row * env.width;
+    let mut layer = match env.params.layer {
+        Some(l) => l[index * 4..index * 4 + 4].try_into().unwrap(),
+        None => return [0.0; 4],
+    };
+    if env.params.premultiplied {
+        unpremultiply(&mut layer)
+    }
+    let mut from = *state;
+    (from.space, from.rgb) = match ops.first() {
+        Some(Operation::Space(space)) => (*space, None),
+        Some(Operation::Rgb(rgb)) => (Space::XYZ, Some(*rgb)),
+        _ => return layer,
+    };
+    match state.rgb {
+        Some(rgb) => from.convert_rgb(&mut layer, rgb),
+        None => from.convert(&mut layer, state.space),
+    }
+    state.layer = Some(layer);
+    layer
+}
+
+/// Runs `ops` once on `pixel`, which is in `state.space`, continuing from whatever `state` holds.
+///
+/// Unlike `process()` the pixel is left in the space the program ended in
+/// and variables are not reset, so it can be called repeatedly to step through code.
+///
+/// Returns true if the program was stopped by the jump limit.
+#[inline]
+pub fn run_pixel(
+    ops: &[Operation],
+    pixel: &mut [f32; 4],
+    state: &mut PixelState,
+    env: &Env,
+) -> bool {
+    run_pixel_with(ops, pixel, state, env, |_, _, _, _, _| ())
+}
+
+/// `run_pixel()` calling `hook` after every operation with its index in `ops`,
+/// the operation, where control goes next, and the resulting pixel and state.
+/// The operation inside a taken `if` is reported with the index of the `if`.
+#[inline]
+fn run_pixel_with<H: FnMut(usize, &Operation, Flow, &[f32; 4], &PixelState)>(
+    ops: &[Operation],
+    pixel: &mut [f32; 4],
+    state: &mut PixelState,
+    env: &Env,
+    mut hook: H,
+) -> bool {
+    // {{{
+    let jump_limit = env.params.jump_limit.unwrap_or(JUMP_LIMIT);
+    let mut goto_breaker = 0;
+    let mut iter = ops.iter();
+    let mut op = match iter.next() {
+        Some(o) => o,
+        None => return false,
+    };
+
+    macro_rules! tar {
+        ($obj:expr) => {
+            match $obj {
+                Obj::Chan(i) => &mut pixel[i],
+                Obj::Var(i) => &mut state.vars[i],
+                _ => panic!("This shouldn't be reachable"),
+            }
+        };
+    }
+
+    macro_rules! src {
+        ($obj:expr) => {
+            match $obj {
+                Obj::Chan(i) => pixel[i],
+                Obj::Var(i) => state.vars[i],
+                Obj::Num(n) => n,
+                Obj::E => E,
+                Obj::Pi => PI,
+                Obj::Rand => {
+                    state.rand_calls += 1;
+                    noise::white(env.params.seed, env.col, env.row, state.rand_calls)
+                }
+                Obj::Col => env.col as f32,
+                Obj::Row => env.row as f32,
+                Obj::Width => env.width as f32,
+                Obj::Height => env.height as f32,
+                Obj::XNorm => env.col as f32 / env.width as f32,
+                Obj::YNorm => env.row as f32 / env.height as f32,
+                Obj::HK2023 => hk_high2023(pixel),
+                Obj::Mask => env.mask,
+                Obj::Frame => env.params.frame as f32,
+                Obj::Time => env.params.time,
+                Obj::Frames => env.params.frames as f32,
+                Obj::InGamut => {
+                    let mut color = [pixel[0], pixel[1], pixel[2]];
+                    if let Some(rgb) = state.rgb {
+                        rgb.to_xyz(&mut color)
+                    }
+                    gamut::in_gamut(&color, state.space) as u8 as f32
+                }
+                Obj::Delta(..) | Obj::DeltaVars(..) => difference($obj, pixel, state),
+                Obj::Layer(i) => layer(ops, env, state)[i],
+                // only valid as a process source, handled there
+                Obj::Color(_) => panic!("This shouldn't be reachable"),
+            }
+        };
+    }
+
+    macro_rules! vector {
+        ($vector:expr) => {
+            match $vector {
+                Vector::Objs([a, b, c]) => [src!(a), src!(b), src!(c)],
+                Vector::All(Obj::Color(c)) => c,
+                // evaluated once, so 'rand' is the same for all three
+                Vector::All(o) => [src!(o); 3],
+            }
+        };
+    }
+
+    loop {
+        let index = ops.len() - iter.len() - 1;
+        let flow = match op {
+            Operation::Process {
+                target,
+                operation,
+                source,
+            } => {
+                let src: f32 = match (*source, *target) {
+                    (Obj::Color(c), Obj::Chan(i)) => c[i],
+                    (source, _) => src!(source),
+                };
+
+                let tar: &mut f32 = tar!(*target);
+
+                apply(operation, tar, src);
+                Some(Flow::Next)
+            }
+            Operation::Vector {
+                target,
+                operation,
+                source,
+            } => {
+                let src = vector!(*source);
+                match operation {
+                    VecOp::Op(operation) => {
+                        for (t, s) in target.iter().zip(src) {
+                            apply(operation, tar!(*t), s)
+                        }
+                    }
+                    VecOp::Mix(amount) => {
+                        let amount = src!(*amount);
+                        for (t, s) in target.iter().zip(src) {
+                            let t = tar!(*t);
+                            *t += (s - *t) * amount
+                        }
+                    }
+                    VecOp::Cross => {
+                        let t = [src!(target[0]), src!(target[1]), src!(target[2])];
+                        let cross = [
+                            t[1] * src[2] - t[2] * src[1],
+                            t[2] * src[0] - t[0] * src[2],
+                            t[0] * src[1] - t[1] * src[0],
+                        ];
+                        for (t, c) in target.iter().zip(cross) {
+                            *tar!(*t) = c
+                        }
+                    }
+                }
+                Some(Flow::Next)
+            }
+            Operation::Dot { target, a, b } => {
+                let (a, b) = (vector!(*a), vector!(*b));
+                *tar!(*target) = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+                Some(Flow::Next)
+            }
+            Operation::Length { target, source } => {
+                let v = vector!(*source);
+                *tar!(*target) = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+                Some(Flow::Next)
+            }
+            Operation::Space(new_space) => {
+                state.convert(pixel, *new_space);
+                Some(Flow::Next)
+            }
+            Operation::Rgb(rgb) => {
+                state.convert_rgb(pixel, *rgb);
+                Some(Flow::Next)
+            }
+            Operation::Adapt { from, to, cat } => {
+                state.in_xyz(pixel, |xyz| rgb::adapt(xyz, *from, *to, *cat));
+                Some(Flow::Next)
+            }
+            Operation::Temperature(kelvin) => {
+                let kelvin = src!(*kelvin);
+                state.in_xyz(pixel, |xyz| rgb::temperature(xyz, kelvin));
+                Some(Flow::Next)
+            }
+            Operation::Tint(tint) => {
+                let tint = src!(*tint);
+                state.in_xyz(pixel, |xyz| rgb::tint(xyz, tint));
+                Some(Flow::Next)
+            }
+            Operation::WhitepointFrom { r, g, b } => {
+                let mut neutral = [src!(*r), src!(*g), src!(*b)];
+                convert_space(Space::SRGB, Space::XYZ, &mut neutral);
+                state.in_xyz(pixel, |xyz| rgb::whitepoint_from(xyz, neutral));
+                Some(Flow::Next)
+            }
+            Operation::Curve {
+                target,
+                operation,
+                curve,
+                source,
+            } => {
+                let src = curve.sample(src!(*source));
+                apply(operation, tar!(*target), src);
+                Some(Flow::Next)
+            }
+            Operation::Gradient {
+                target,
+                operation,
+                gradient,
+                source,
+            } => {
+                let src = gradient.sample(src!(*source));
+                for (t, s) in target.iter().zip(src) {
+                    apply(operation, tar!(*t), s)
+                }
+                Some(Flow::Next)
+            }
+            Operation::Blend { mode, opacity } => {
+                let opacity = src!(*opacity);
+                blend::blend(*mode, pixel, layer(ops, env, state), opacity, state);
+                Some(Flow::Next)
+            }
+            Operation::If {
+                left,
+                cmp,
+                right,
+                then,
+            } => {
+                let left = src!(*left);
+
+                let right = src!(*right);
+
+                let taken = match cmp {
+                    Cmp::Eq => left == right,
+                    Cmp::NEq => left != right,
+                    Cmp::Gt => left > right,
+                    Cmp::Lt => left < right,
+                    Cmp::GtEq => left >= right,
+                    Cmp::LtEq => left <= right,
+                };
+                hook(index, op, Flow::If(taken), pixel, state);
+                if taken {
+                    op = then.as_ref();
+                    continue;
+                }
+                None
+            }
+            Operation::Goto(i) => {
+                if goto_breaker < jump_limit {
+                    iter = ops[*i..].iter();
+                    goto_breaker += 1;
+                    Some(Flow::Jump(*i))
+                } else {
+                    hook(index, op, Flow::JumpLimit, pixel, state);
+                    return true;
+                }
+            }
+            Operation::GotoTmp(_) => panic!("GotoTmp shouldn't be sent to process!"),
+            // hypothetically should be safe, as the pointers can't be uninitialized?
+            Operation::Swap { t1, t2 } => {
+                unsafe { std::ptr::swap(tar!(*t1), tar!(*t2)) };
+                Some(Flow::Next)
+            }
+            Operation::Noise {
+                target,
+                noise,
+                x,
+                y,
+                octaves,
+                seed,
+            } => {
+                let (x, y) = (src!(*x), src!(*y));
+                let seed = noise::hash(env.params.seed, src!(*seed).to_bits() as u64, 0, 0);
+                let result = match noise {
+                    Noise::Value => noise::value(seed, x, y),
+                    Noise::Perlin => noise::perlin(seed, x, y),
+                    Noise::Simplex => noise::simplex(seed, x, y),
+                    Noise::Worley => noise::worley(seed, x, y),
+                    Noise::Fbm => noise::fbm(seed, x, y, src!(*octaves) as u32),
+                };
+                *tar!(*target) = result;
+                Some(Flow::Next)
+            }
+            Operation::Gamut(mapping) => {
+                let mut color = [pixel[0], pixel[1], pixel[2]];
+                if let Some(rgb) = state.rgb {
+                    rgb.to_xyz(&mut color)
+                }
+                match mapping {
+                    Gamut::Clip => gamut::clip(&mut color, state.space),
+                    Gamut::Chroma => gamut::chroma(&mut color, state.space),
+                    Gamut::Project => gamut::project(&mut color, state.space),
+                }
+                if let Some(rgb) = state.rgb {
+                    rgb.from_xyz(&mut color)
+                }
+                pixel[..3].copy_from_slice(&color);
+                Some(Flow::Next)
+            }
+        };
+        if let Some(flow) = flow {
+            hook(index, op, flow, pixel, state);
+        }
+        match iter.next() {
+            Some(o) => op = o,
+            None => return false,
+        }
+    }
+} // }}}
+
 // TODO: make run-able without alpha.
 // TODO: Result<> instead of panic
 fn process_segment<O: AsRef<[Operation]>>(
     ops: O,
     pixels: &mut [f32],
-    x: usize,
-    y: usize,
+    start: usize,
     width: usize,
     height: usize,
-    externals: Option<[f32; 9]>,
-) {
-    // {{{
-    assert!(pixels.len() % 4 == 0);
+    params: &Params,
+    mut profile: Option<&mut Profile>,
+) -> usize {
+    assert!(pixels.len().is_multiple_of(4));
 
     let ops: &[Operation] = ops.as_ref();
 
     // needs an initial Space for reference
-    let mut space = match ops.get(0) {
-        Some(op) => match op {
-            Operation::Space(space) => space,
-            _ => return,
-        },
-        None => return,
+    let orig_space = match ops.first() {
+        Some(Operation::Space(space)) => *space,
+        _ => return 0,
     };
 
-    let orig_space = space;
-    let defaults: [f32; 18] = {
-        let e = externals.unwrap_or([0.0_f32; 9]);
-        [
-            0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, e[0], e[1], e[2], e[3], e[4], e[5], e[6],
-            e[7], e[8],
-        ]
-    };
+    let defaults = PixelState::new(orig_space, params);
+    let mut limited = 0;
 
     for (n, pixel) in pixels.chunks_exact_mut(4).enumerate() {
         let pixel: &mut [f32; 4] = pixel.try_into().unwrap();
-        // reset space transforms for each pixel
-        space = orig_space;
-        // reset vars each iter
-        let mut v: [f32; 18] = defaults;
-        let mut goto_breaker = 0;
-        let mut iter = ops.iter();
-        let mut op = match iter.next() {
-            Some(o) => o,
-            None => return,
+        let index = n + start;
+        let mask = params.mask.map_or(1.0, |m| m[index]);
+        if mask == 0.0 {
+            continue;
+        }
+        let orig = *pixel;
+        if params.premultiplied {
+            unpremultiply(pixel)
+        }
+        // reset space and vars each pixel
+        let mut state = defaults;
+        let env = Env {
+            col: index % width,
+            row: index / width,
+            width,
+            height,
+            mask,
+            params,
         };
+        if match profile.as_deref_mut() {
+            None => run_pixel(ops, pixel, &mut state, &env),
+            Some(profile) => profile.run(ops, pixel, &mut state, &env),
+        } {
+            limited += 1;
+        }
+        // restore to original if not already
+        if !state.is_in(orig_space) {
+            let timer = profile.is_some().then(Instant::now);
+            state.convert(pixel, orig_space);
+            if let (Some(profile), Some(timer)) = (profile.as_deref_mut(), timer) {
+                profile.conversion += timer.elapsed();
+            }
+        }
+        if params.premultiplied || params.lock_alpha {
+            restore_alpha(pixel, &orig, params)
+        }
+        if mask != 1.0 {
+            pixel
+                .iter_mut()
+                .zip(orig)
+                .for_each(|(p, o)| *p = o + (*p - o) * mask);
+        }
+    }
+    limited
+}
 
-        macro_rules! tar {
-            ($obj:expr) => {
-                match $obj {
-                    Obj::Chan(i) => &mut pixel[i],
-                    Obj::Var(i) => &mut v[i],
-                    _ => panic!("This shouldn't be reachable"),
-                }
-            };
-        }
-
-        macro_rules! src {
-            ($obj:expr) => {
-                match $obj {
-                    Obj::Chan(i) => pixel[i],
-                    Obj::Var(i) => v[i],
-                    Obj::Num(n) => n,
-                    Obj::E => E,
-                    Obj::Pi => PI,
-                    Obj::Rand => fastrand::f32(),
-                    Obj::Col => ((n + x + y * width) % width) as f32,
-                    Obj::Row => ((n + x + y * width) / width) as f32,
-                    Obj::Width => width as f32,
-                    Obj::Height => height as f32,
-                    Obj::XNorm => (((n + x + y * width) % width) as f32) / width as f32,
-                    Obj::YNorm => (((n + x + y * width) / width) as f32) / height as f32,
-                    Obj::HK2023 => hk_high2023(pixel),
-                }
-            };
+/// One executed operation, as recorded by `trace()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    /// Index of the operation in the traced ops
+    pub index: usize,
+    pub op: Operation,
+    pub flow: Flow,
+    /// Space, channels, and variables after the operation ran
+    pub space: Space,
+    /// Custom RGB space the channels are in, if any
+    pub rgb: Option<Rgb>,
+    pub pixel: [f32; 4],
+    /// v1 through v9 then e1 through e9
+    pub vars: [f32; 18],
+}
+
+/// Runs `ops` on the single pixel at `x`, `y` exactly as `process_with()` would,
+/// recording every operation executed along the way.
+///
+/// Returns the steps and the final pixel, or None if the coordinate is outside the image.
+/// `pixels` is not modified.
+pub fn trace<O: AsRef<[Operation]>>(
+    ops: O,
+    pixels: &[f32],
+    width: usize,
+    x: usize,
+    y: usize,
+    params: &Params,
+) -> Option<(Vec<Step>, [f32; 4])> {
+    // {{{
+    let ops: &[Operation] = ops.as_ref();
+    let height = (pixels.len() / 4).checked_div(width).unwrap_or(0);
+    if x >= width || y >= height {
+        return None;
+    }
+    let index = x + y * width;
+    let mut pixel: [f32; 4] = pixels[index * 4..index * 4 + 4].try_into().unwrap();
+    let orig = pixel;
+    let mask = params.mask.map_or(1.0, |m| m[index]);
+
+    let orig_space = match ops.first() {
+        Some(Operation::Space(space)) => *space,
+        _ => return Some((Vec::new(), pixel)),
+    };
+    if mask == 0.0 {
+        return Some((Vec::new(), pixel));
+    }
+
+    let mut steps = Vec::new();
+    let mut state = PixelState::new(orig_space, params);
+    if params.premultiplied {
+        unpremultiply(&mut pixel)
+    }
+    run_pixel_with(
+        ops,
+        &mut pixel,
+        &mut state,
+        &Env {
+            col: x,
+            row: y,
+            width,
+            height,
+            mask,
+            params,
+        },
+        |index, op, flow, pixel, state| {
+            steps.push(Step {
+                index,
+                op: op.clone(),
+                flow,
+                space: state.space,
+                rgb: state.rgb,
+                pixel: *pixel,
+                vars: state.vars,
+            })
+        },
+    );
+    if !state.is_in(orig_space) {
+        state.convert(&mut pixel, orig_space)
+    }
+    if params.premultiplied || params.lock_alpha {
+        restore_alpha(&mut pixel, &orig, params)
+    }
+    if mask != 1.0 {
+        pixel
+            .iter_mut()
+            .zip(orig)
+            .for_each(|(p, o)| *p = o + (*p - o) * mask);
+    }
+    Some((steps, pixel))
+} // }}}
+
+/// Where the work went during `process_profile()`.
+///
+/// Times are summed over every thread, so they can add up to more than the wall time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    /// Times each operation ran, by index in the ops.
+    /// An `if` counts once whether or not it runs its operation
+    pub counts: Vec<u64>,
+    /// Pixels processed
+    pub pixels: usize,
+    /// Pixels that stopped early after hitting the jump limit
+    pub jump_limited: usize,
+    /// Time in space conversions, including converting back at the end of each pixel
+    pub conversion: Duration,
+    /// Time in every other operation
+    pub arithmetic: Duration,
+}
+
+impl Profile {
+    fn new(ops: usize) -> Self {
+        Self {
+            counts: vec![0; ops],
+            ..Default::default()
         }
+    }
 
-        loop {
+    /// `run_pixel()` while timing and counting each operation
+    fn run(
+        &mut self,
+        ops: &[Operation],
+        pixel: &mut [f32; 4],
+        state: &mut PixelState,
+        env: &Env,
+    ) -> bool {
+        let mut timer = Instant::now();
+        let limited = run_pixel_with(ops, pixel, state, env, |index, op, flow, _, _| {
+            let now = Instant::now();
             match op {
-                Operation::Process {
-                    target,
-                    operation,
-                    source,
-                } => {
-                    let src: f32 = src!(*source);
-
-                    let tar: &mut f32 = tar!(*target);
-
-                    match operation {
-                        // Base
-                        Op::Add => *tar += src,
-                        Op::Sub => *tar -= src,
-                        Op::Mul => *tar *= src,
-                        Op::Div => *tar /= src,
-                        Op::Mod => *tar %= src,
-                        Op::Pow => *tar = tar.powf(src),
-                        Op::Set => *tar = src,
-                        // Extended
-                        Op::Abs => *tar = src.abs(),
-                        Op::Acos => *tar = src.acos(),
-                        Op::Acosh => *tar = src.acosh(),
-                        Op::Asin => *tar = src.asin(),
-                        Op::Asinh => *tar = src.asinh(),
-                        Op::Atan => *tar = src.atan(),
-                        Op::Atan2 => *tar = tar.atan2(src),
-                        Op::Atanh => *tar = src.atanh(),
-                        Op::Cbrt => *tar = src.cbrt(),
-                        Op::Ceil => *tar = src.ceil(),
-                        Op::Copysign => *tar = tar.copysign(src),
-                        Op::Cos => *tar = src.cos(),
-                        Op::Cosh => *tar = src.cosh(),
-                        Op::Degrees => *tar = src.to_degrees(),
-                        Op::Diveuclid => *tar = tar.div_euclid(src),
-                        Op::Exp => *tar = src.exp(),
-                        Op::Exp2 => *tar = src.exp2(),
-                        Op::Expm1 => *tar = src.exp_m1(),
-                        Op::Floor => *tar = src.floor(),
-                        Op::Fract => *tar = src.fract(),
-                        Op::Hypot => *tar = tar.hypot(src),
-                        Op::Ln => *tar = src.ln(),
-                        Op::Ln1p => *tar = src.ln_1p(),
-                        Op::Log => *tar = tar.log(src),
-                        Op::Log10 => *tar = src.log10(),
-                        Op::Log2 => *tar = src.log2(),
-                        Op::Max => *tar = tar.max(src),
-                        Op::Min => *tar = tar.min(src),
-                        Op::Radians => *tar = src.to_radians(),
-                        Op::Recip => *tar = src.recip(),
-                        Op::Remeuclid => *tar = tar.rem_euclid(src),
-                        Op::Round => *tar = src.round(),
-                        Op::Signum => *tar = src.signum(),
-                        Op::Sin => *tar = src.sin(),
-                        Op::Sinh => *tar = src.sinh(),
-                        Op::Sqrt => *tar = src.sqrt(),
-                        Op::Tan => *tar = src.tan(),
-                        Op::Tanh => *tar = src.tanh(),
-                        Op::Trunc => *tar = src.trunc(),
-                        // Custom
-                        Op::Invert => *tar = src - *tar,
-                    };
-                }
-                Operation::Space(new_space) => {
-                    convert_space(*space, *new_space, pixel);
-                    space = new_space;
-                }
-                Operation::If {
-                    left,
-                    cmp,
-                    right,
-                    then,
-                } => {
-                    let left = src!(*left);
-
-                    let right = src!(*right);
-
-                    if match cmp {
-                        Cmp::Eq => left == right,
-                        Cmp::NEq => left != right,
-                        Cmp::Gt => left > right,
-                        Cmp::Lt => left < right,
-                        Cmp::GtEq => left >= right,
-                        Cmp::LtEq => left <= right,
-                    } {
-                        op = then.as_ref();
-                        continue;
-                    }
-                }
-                Operation::Goto(i) => {
-                    if goto_breaker < 100 {
-                        iter = ops[*i..].iter();
-                        goto_breaker += 1;
-                    } else {
-                        break;
-                    }
-                }
-                Operation::GotoTmp(_) => panic!("GotoTmp shouldn't be sent to process!"),
-                // hypothetically should be safe, as the pointers can't be uninitialized?
-                Operation::Swap { t1, t2 } => unsafe {
-                    std::ptr::swap(tar!(*t1), tar!(*t2));
-                },
+                Operation::Space(_) | Operation::Rgb(_) => self.conversion += now - timer,
+                _ => self.arithmetic += now - timer,
             }
-            match iter.next() {
-                Some(o) => op = o,
-                None => break,
+            timer = now;
+            // an if's operation shares its index
+            if std::ptr::eq(op, &ops[index]) {
+                self.counts[index] += 1;
             }
-        }
-        // restore to original if not already
-        if space != orig_space {
-            convert_space(*space, *orig_space, pixel)
+            if flow == Flow::JumpLimit {
+                self.jump_limited += 1;
+            }
+        });
+        self.pixels += 1;
+        limited
+    }
+
+    fn merge(&mut self, other: &Profile) {
+        self.counts
+            .iter_mut()
+            .zip(&other.counts)
+            .for_each(|(a, b)| *a += b);
+        self.pixels += other.pixels;
+        self.jump_limited += other.jump_limited;
+        self.conversion += other.conversion;
+        self.arithmetic += other.arithmetic;
+    }
+}
+
+/// Pixels per tile. Progress is reported and cancellation is checked between tiles.
+const TILE_SIZE: usize = 16384;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessError {
+    /// The cancellation token was set before every tile finished.
+    /// Unfinished tiles are left untouched.
+    Cancelled,
+    /// This many pixels hit the jump limit, with `Params::jump_limit_error` set.
+    /// Every pixel is still processed.
+    JumpLimit(usize),
+}
+
+impl std::fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessError::Cancelled => write!(f, "Processing cancelled"),
+            ProcessError::JumpLimit(n) => write!(f, "{} pixels hit the jump limit", n),
         }
     }
-} // }}}
+}
+
+impl std::error::Error for ProcessError {}
 
 pub fn process<O: AsRef<[Operation]>>(
     ops: O,
     pixels: &mut [f32],
-    mut width: usize,
+    width: usize,
     externals: Option<[f32; 9]>,
 ) {
+    process_with(
+        ops,
+        pixels,
+        width,
+        &Params {
+            externals,
+            ..Default::default()
+        },
+    );
+}
+
+/// Same as `process()` with every `Params` available.
+///
+/// Returns how many pixels hit the jump limit. `Params::jump_limit_error` is ignored.
+pub fn process_with<O: AsRef<[Operation]>>(
+    ops: O,
+    pixels: &mut [f32],
+    width: usize,
+    params: &Params,
+) -> usize {
+    // never cancelled so only ever fails on the jump limit
+    match process_progress(ops, pixels, width, params, |_| (), &AtomicBool::new(false)) {
+        Ok(n) | Err(ProcessError::JumpLimit(n)) => n,
+        Err(ProcessError::Cancelled) => unreachable!(),
+    }
+}
+
+/// Same as `process_with()` but calls `progress` with the fraction of pixels done after every tile
+/// and stops early if `cancel` is set.
+///
+/// `progress` is called from the worker threads, possibly several at once.
+///
+/// Returns how many pixels hit the jump limit.
+pub fn process_progress<O: AsRef<[Operation]>, F: Fn(f32) + Sync>(
+    ops: O,
+    pixels: &mut [f32],
+    width: usize,
+    params: &Params,
+    progress: F,
+    cancel: &AtomicBool,
+) -> Result<usize, ProcessError> {
+    process_tiles(ops.as_ref(), pixels, width, params, progress, cancel, None)
+}
+
+/// Same as `process_with()` but also counts and times every operation.
+/// Noticeably slower, so only for finding what makes a script slow.
+pub fn process_profile<O: AsRef<[Operation]>>(
+    ops: O,
+    pixels: &mut [f32],
+    width: usize,
+    params: &Params,
+) -> Profile {
     let ops: &[Operation] = ops.as_ref();
-    let height = if width == 0 {
-        width = usize::MAX;
-        usize::MAX
-    } else {
-        pixels.len() / 4 / width
+    let profile = Mutex::new(Profile::new(ops.len()));
+    // never cancelled so always Ok
+    let _ = process_tiles(
+        ops,
+        pixels,
+        width,
+        params,
+        |_| (),
+        &AtomicBool::new(false),
+        Some(&profile),
+    );
+    profile.into_inner().unwrap()
+}
+
+fn process_tiles<F: Fn(f32) + Sync>(
+    ops: &[Operation],
+    pixels: &mut [f32],
+    mut width: usize,
+    params: &Params,
+    progress: F,
+    cancel: &AtomicBool,
+    profile: Option<&Mutex<Profile>>,
+) -> Result<usize, ProcessError> {
+    if let Some(mask) = params.mask {
+        assert!(mask.len() >= pixels.len() / 4, "Mask smaller than image");
+    }
+    if let Some(layer) = params.layer {
+        assert!(layer.len() >= pixels.len(), "Layer smaller than image");
+    }
+    let height = match (pixels.len() / 4).checked_div(width) {
+        Some(h) => h,
+        None => {
+            width = usize::MAX;
+            usize::MAX
+        }
     };
 
-    if pixels.len() < 400 {
+    // (index of first pixel, pixels) for each contiguous run to process
+    let segments: Vec<(usize, &mut [f32])> = match params.roi {
+        Some(roi) => {
+            assert!(
+                width != usize::MAX && roi.x + roi.width <= width && roi.y + roi.height <= height,
+                "Region of interest outside image"
+            );
+            pixels
+                .chunks_exact_mut(width * 4)
+                .enumerate()
+                .skip(roi.y)
+                .take(roi.height)
+                .map(|(row, pixels)| {
+                    (
+                        row * width + roi.x,
+                        &mut pixels[roi.x * 4..(roi.x + roi.width) * 4],
+                    )
+                })
+                .collect()
+        }
+        None => vec![(0, pixels)],
+    };
+
+    let total: usize = segments.iter().map(|(_, seg)| seg.len() / 4).sum();
+    let threads = if total < 100 {
         // < 10x10 grid always single thread.
         // dumb way to make sure it splits well + overhead avoidance.
-        process_segment(ops, pixels, 0, 0, width, height, externals)
+        1
+    } else if params.threads > 0 {
+        params.threads
     } else {
-        let chunk_size: usize = pixels.len() / 4 / thread::available_parallelism().unwrap() * 4;
-        let chunks: Vec<(usize, &mut [f32])> = pixels.chunks_mut(chunk_size).enumerate().collect();
+        thread::available_parallelism().unwrap().get()
+    };
+    let tile_size = (total / threads).clamp(1, TILE_SIZE);
+    let mut tiles: Vec<(usize, &mut [f32])> = segments
+        .into_iter()
+        .flat_map(|(start, seg)| {
+            seg.chunks_mut(tile_size * 4)
+                .enumerate()
+                .map(move |(n, tile)| (start + tile_size * n, tile))
+        })
+        .collect();
+    let done = AtomicUsize::new(0);
+    let limited = AtomicUsize::new(0);
+
+    let run = |tiles: &mut [(usize, &mut [f32])]| -> Result<(), ProcessError> {
+        for (start, tile) in tiles.iter_mut() {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(ProcessError::Cancelled);
+            }
+            let mut local = profile.map(|_| Profile::new(ops.len()));
+            let n = process_segment(ops, tile, *start, width, height, params, local.as_mut());
+            limited.fetch_add(n, Ordering::Relaxed);
+            if let (Some(profile), Some(local)) = (profile, local) {
+                profile.lock().unwrap().merge(&local);
+            }
+            let done = done.fetch_add(tile.len() / 4, Ordering::Relaxed) + tile.len() / 4;
+            progress(done as f32 / total as f32);
+        }
+        Ok(())
+    };
+
+    if threads == 1 {
+        run(&mut tiles)
+    } else {
+        let per_thread = tiles.len().div_ceil(threads);
+        let run = &run;
         thread::scope(|scoped| {
-            chunks
-                .into_iter()
-                .map(|(n, chunk)| {
-                    scoped.spawn(move || {
-                        process_segment(
-                            ops,
-                            chunk,
-                            (chunk_size / 4 * n) % width,
-                            (chunk_size / 4 * n) / width,
-                            width,
-                            height,
-                            externals,
-                        )
-                    })
-                })
-                .collect::<Vec<ScopedJoinHandle<()>>>()
+            tiles
+                .chunks_mut(per_thread)
+                .map(|group| scoped.spawn(move || run(group)))
+                .collect::<Vec<ScopedJoinHandle<Result<(), ProcessError>>>>()
                 .into_iter()
-                .for_each(|jh| jh.join().unwrap());
-        });
+                .try_for_each(|jh| jh.join().unwrap())
+        })
+    }?;
+
+    match limited.into_inner() {
+        n if n > 0 && params.jump_limit_error => Err(ProcessError::JumpLimit(n)),
+        n => Ok(n),
     }
 }
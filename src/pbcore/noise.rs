@@ -0,0 +1,160 @@
+//! Counter-based random numbers and procedural noise.
+//!
+//! Everything here is a pure function of its inputs,
+//! so results never depend on thread count or processing order.
+
+/// SplitMix64 finalizer
+fn mix(mut h: u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+    h ^ (h >> 31)
+}
+
+/// Hashes a seed and three integer coordinates into 64 well mixed bits
+pub fn hash(seed: u64, x: u64, y: u64, z: u64) -> u64 {
+    let mut h = mix(seed.wrapping_add(0x9E3779B97F4A7C15));
+    h = mix(h ^ x);
+    h = mix(h ^ y.wrapping_mul(0x9E3779B97F4A7C15));
+    mix(h ^ z.wrapping_mul(0xC2B2AE3D27D4EB4F))
+}
+
+/// Maps the top 24 bits of a hash to 0.0 -> 1.0 exclusive
+pub fn unit(h: u64) -> f32 {
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// White noise for pixel `x`, `y`. `call` is the nth read of `rand` on that pixel.
+pub fn white(seed: u64, x: usize, y: usize, call: u32) -> f32 {
+    unit(hash(seed, x as u64, y as u64, call as u64))
+}
+
+// Coherent noise {{{
+
+/// Quintic fade curve
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn lattice(seed: u64, x: i64, y: i64) -> u64 {
+    hash(seed, x as u64, y as u64, 0)
+}
+
+/// Unit gradient for a lattice point, dotted with the offset `dx`, `dy`
+fn grad(seed: u64, x: i64, y: i64, dx: f32, dy: f32) -> f32 {
+    let angle = unit(lattice(seed, x, y)) * std::f32::consts::TAU;
+    angle.cos() * dx + angle.sin() * dy
+}
+
+/// Value noise. Random values on the integer lattice smoothly interpolated. 0.0 -> 1.0
+pub fn value(seed: u64, x: f32, y: f32) -> f32 {
+    let (xf, yf) = (x.floor(), y.floor());
+    let (xi, yi) = (xf as i64, yf as i64);
+    let (u, v) = (fade(x - xf), fade(y - yf));
+    lerp(
+        lerp(
+            unit(lattice(seed, xi, yi)),
+            unit(lattice(seed, xi + 1, yi)),
+            u,
+        ),
+        lerp(
+            unit(lattice(seed, xi, yi + 1)),
+            unit(lattice(seed, xi + 1, yi + 1)),
+            u,
+        ),
+        v,
+    )
+}
+
+/// Classic gradient noise. 0.0 -> 1.0, 0.5 on every integer coordinate
+pub fn perlin(seed: u64, x: f32, y: f32) -> f32 {
+    let (xf, yf) = (x.floor(), y.floor());
+    let (xi, yi) = (xf as i64, yf as i64);
+    let (dx, dy) = (x - xf, y - yf);
+    let (u, v) = (fade(dx), fade(dy));
+    let n = lerp(
+        lerp(
+            grad(seed, xi, yi, dx, dy),
+            grad(seed, xi + 1, yi, dx - 1.0, dy),
+            u,
+        ),
+        lerp(
+            grad(seed, xi, yi + 1, dx, dy - 1.0),
+            grad(seed, xi + 1, yi + 1, dx - 1.0, dy - 1.0),
+            u,
+        ),
+        v,
+    );
+    // unit gradients peak at ±sqrt(0.5)
+    (n * std::f32::consts::SQRT_2 * 0.5 + 0.5).clamp(0.0, 1.0)
+}
+
+/// 2D simplex noise. Fewer directional artifacts than `perlin`. 0.0 -> 1.0
+pub fn simplex(seed: u64, x: f32, y: f32) -> f32 {
+    // (sqrt(3) - 1) / 2, (3 - sqrt(3)) / 6
+    const F2: f32 = 0.3660254;
+    const G2: f32 = 0.21132487;
+
+    let s = (x + y) * F2;
+    let (i, j) = ((x + s).floor(), (y + s).floor());
+    let t = (i + j) * G2;
+    let (x0, y0) = (x - (i - t), y - (j - t));
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let (i, j) = (i as i64, j as i64);
+
+    let corners = [
+        (i, j, x0, y0),
+        (i + i1, j + j1, x0 - i1 as f32 + G2, y0 - j1 as f32 + G2),
+        (i + 1, j + 1, x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2),
+    ];
+
+    let n: f32 = corners
+        .into_iter()
+        .map(|(ci, cj, dx, dy)| {
+            let t = 0.5 - dx * dx - dy * dy;
+            if t < 0.0 {
+                0.0
+            } else {
+                t.powi(4) * grad(seed, ci, cj, dx, dy)
+            }
+        })
+        .sum();
+
+    // 70 is the usual scale for gradients of length sqrt(2)
+    (n * 70.0 * std::f32::consts::SQRT_2 * 0.5 + 0.5).clamp(0.0, 1.0)
+}
+
+/// Cellular noise. Distance to the nearest of one random point per lattice cell.
+/// 0.0 on a point, rarely above 1.0
+pub fn worley(seed: u64, x: f32, y: f32) -> f32 {
+    let (xf, yf) = (x.floor(), y.floor());
+    let (xi, yi) = (xf as i64, yf as i64);
+    let mut nearest = f32::INFINITY;
+    for cy in yi - 1..=yi + 1 {
+        for cx in xi - 1..=xi + 1 {
+            let h = lattice(seed, cx, cy);
+            let px = cx as f32 + unit(h);
+            let py = cy as f32 + unit(mix(h));
+            nearest = nearest.min((px - x).hypot(py - y));
+        }
+    }
+    nearest
+}
+
+/// Fractal Brownian motion. `octaves` layers of `perlin`,
+/// each at double the frequency and half the amplitude of the last. Max 16 octaves. 0.0 -> 1.0
+pub fn fbm(seed: u64, x: f32, y: f32, octaves: u32) -> f32 {
+    let (mut sum, mut total, mut amp, mut freq) = (0.0, 0.0, 1.0, 1.0);
+    for octave in 0..octaves.clamp(1, 16) {
+        sum += (perlin(mix(seed ^ octave as u64), x * freq, y * freq) - 0.5) * amp;
+        total += amp;
+        amp *= 0.5;
+        freq *= 2.0;
+    }
+    sum / total + 0.5
+}
+
+// }}}
@@ -1,9 +1,13 @@
-use super::Space;
+use super::{color, Cat, Curve, Delta, Gradient, Illuminant, Interp, Rgb, Space};
+
+use colcon::convert_space;
 
 use std::collections::HashMap;
 
 // structs {{{
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Op {
     // Base
     Add,
@@ -58,6 +62,8 @@ pub enum Op {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Cmp {
     Gt,
     Lt,
@@ -67,7 +73,54 @@ pub enum Cmp {
     LtEq,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Noise {
+    Value,
+    Perlin,
+    Simplex,
+    Worley,
+    Fbm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Gamut {
+    Clip,
+    Chroma,
+    Project,
+}
+
+/// How `blend` combines the pixel with the second layer on top
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Blend {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    SoftLight,
+    HardLight,
+    Darken,
+    Lighten,
+    Difference,
+    Exclusion,
+    Add,
+    Subtract,
+    ColorDodge,
+    ColorBurn,
+    Hue,
+    Saturation,
+    Color,
+    Luminosity,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Obj {
     Chan(usize),
     Var(usize),
@@ -82,11 +135,50 @@ pub enum Obj {
     XNorm,
     YNorm,
     HK2023,
+    Mask,
+    Frame,
+    Time,
+    Frames,
+    InGamut,
+    /// A colour literal, in the space active where it's used.
+    /// Reads the channel matching the target
+    Color([f32; 3]),
+    /// Difference between the pixel and a colour literal, converted to `Delta::space()`
+    Delta(Delta, [f32; 3]),
+    /// Difference between the pixel and three variables in the current space.
+    /// Indices are like `Var`, as bytes to keep `Obj` small
+    DeltaVars(Delta, [u8; 3]),
+    /// Channel of the second layer, converted to the current space
+    Layer(usize),
+}
+
+/// Source of a vector operation
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Vector {
+    /// Three channels or variables, like `rgb` or `v1v2v3`
+    Objs([Obj; 3]),
+    /// One source for all three. Colour literals give each channel its own value
+    All(Obj),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum VecOp {
+    /// Any scalar operation, channel by channel
+    Op(Op),
+    /// Move toward the source by this amount, 0.0 -> 1.0
+    Mix(Obj),
+    Cross,
 }
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Operation {
-    Space(Space),
+    Space(#[cfg_attr(feature = "serde", serde(with = "space_serde"))] Space),
     Process {
         target: Obj,
         operation: Op,
@@ -104,10 +196,80 @@ pub enum Operation {
         t1: Obj,
         t2: Obj,
     },
+    Noise {
+        target: Obj,
+        noise: Noise,
+        x: Obj,
+        y: Obj,
+        octaves: Obj,
+        seed: Obj,
+    },
+    Gamut(Gamut),
+    /// Switch to an RGB space colcon doesn't have
+    Rgb(Rgb),
+    /// Chromatic adaptation through XYZ, staying in the current space
+    Adapt {
+        from: Illuminant,
+        to: Illuminant,
+        cat: Cat,
+    },
+    /// White balance for a light of this many kelvin
+    Temperature(Obj),
+    /// White balance for a light this far toward green
+    Tint(Obj),
+    /// White balance making this sRGB colour neutral
+    WhitepointFrom {
+        r: Obj,
+        g: Obj,
+        b: Obj,
+    },
+    /// Three channels or variables at once
+    Vector {
+        target: [Obj; 3],
+        operation: VecOp,
+        source: Vector,
+    },
+    Dot {
+        target: Obj,
+        a: Vector,
+        b: Vector,
+    },
+    Length {
+        target: Obj,
+        source: Vector,
+    },
+    /// Blend the second layer over the pixel
+    Blend {
+        mode: Blend,
+        opacity: Obj,
+    },
+    /// Looks up a curve declared with `curve`
+    Curve {
+        target: Obj,
+        operation: Op,
+        curve: Box<Curve>,
+        source: Obj,
+    },
+    /// Looks up a gradient map declared with `gradient` into three channels or variables
+    Gradient {
+        target: [Obj; 3],
+        operation: Op,
+        gradient: Box<Gradient>,
+        source: Obj,
+    },
+}
+
+/// A `curve` or `gradient` declaration, which lookups are linked to by name
+#[derive(Clone, Debug, PartialEq)]
+enum Table {
+    Curve(Curve),
+    Gradient(Gradient),
 }
 // }}}
 
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum OpError {
     Partial { line: usize, details: String },
     Unknown { line: usize },
@@ -123,8 +285,462 @@ impl std::fmt::Display for OpError {
     }
 }
 
+/// What a `Warning` is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Lint {
+    /// A variable is read before anything sets it
+    Unset,
+    /// A variable is set but nothing reads it after
+    Unused,
+    /// An external is written to
+    External,
+    /// A channel name from before a space change
+    Stale,
+    /// A statement that changes nothing
+    NoEffect,
+    /// `==` or `!=` on fractional values
+    ExactCmp,
+    /// A loop with no way out before the jump limit
+    Runaway,
+}
+
+/// Code that parses fine but probably doesn't do what was meant.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Warning {
+    pub line: usize,
+    pub kind: Lint,
+    pub details: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} on line {}", self.details, self.line)
+    }
+}
+
+// Display {{{
+
+impl std::fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            // Base
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Pow => "**",
+            Op::Set => "=",
+            // Extended
+            Op::Abs => "abs",
+            Op::Acos => "acos",
+            Op::Acosh => "acosh",
+            Op::Asin => "asin",
+            Op::Asinh => "asinh",
+            Op::Atan => "atan",
+            Op::Atan2 => "atan2",
+            Op::Atanh => "atanh",
+            Op::Cbrt => "cbrt",
+            Op::Ceil => "ceil",
+            Op::Copysign => "copysign",
+            Op::Cos => "cos",
+            Op::Cosh => "cosh",
+            Op::Degrees => "degrees",
+            Op::Diveuclid => "diveuclid",
+            Op::Exp => "exp",
+            Op::Exp2 => "exp2",
+            Op::Expm1 => "expm1",
+            Op::Floor => "floor",
+            Op::Fract => "fract",
+            Op::Hypot => "hypot",
+            Op::Ln => "ln",
+            Op::Ln1p => "ln1p",
+            Op::Log => "log",
+            Op::Log2 => "log2",
+            Op::Log10 => "log10",
+            Op::Max => "max",
+            Op::Min => "min",
+            Op::Radians => "radians",
+            Op::Recip => "recip",
+            Op::Remeuclid => "remeuclid",
+            Op::Round => "round",
+            Op::Signum => "signum",
+            Op::Sin => "sin",
+            Op::Sinh => "sinh",
+            Op::Sqrt => "sqrt",
+            Op::Tan => "tan",
+            Op::Tanh => "tanh",
+            Op::Trunc => "trunc",
+            // Custom
+            Op::Invert => "invert",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::fmt::Display for Cmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Cmp::Gt => ">",
+            Cmp::Lt => "<",
+            Cmp::Eq => "==",
+            Cmp::NEq => "!=",
+            Cmp::GtEq => ">=",
+            Cmp::LtEq => "<=",
+        })
+    }
+}
+
+impl std::fmt::Display for Gamut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Gamut::Clip => "clip",
+            Gamut::Chroma => "chroma",
+            Gamut::Project => "project",
+        })
+    }
+}
+
+impl std::fmt::Display for Blend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Blend::Normal => "normal",
+            Blend::Multiply => "multiply",
+            Blend::Screen => "screen",
+            Blend::Overlay => "overlay",
+            Blend::SoftLight => "softlight",
+            Blend::HardLight => "hardlight",
+            Blend::Darken => "darken",
+            Blend::Lighten => "lighten",
+            Blend::Difference => "difference",
+            Blend::Exclusion => "exclusion",
+            Blend::Add => "add",
+            Blend::Subtract => "subtract",
+            Blend::ColorDodge => "colordodge",
+            Blend::ColorBurn => "colorburn",
+            Blend::Hue => "hue",
+            Blend::Saturation => "saturation",
+            Blend::Color => "color",
+            Blend::Luminosity => "luminosity",
+        })
+    }
+}
+
+impl std::fmt::Display for Noise {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Noise::Value => "value",
+            Noise::Perlin => "perlin",
+            Noise::Simplex => "simplex",
+            Noise::Worley => "worley",
+            Noise::Fbm => "fbm",
+        })
+    }
+}
+
+impl std::fmt::Display for Delta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Delta::E76 => "deltae76",
+            Delta::E2000 => "deltae2000",
+            Delta::Ok => "deltaok",
+        })
+    }
+}
+
+impl std::fmt::Display for Rgb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Rgb::DisplayP3 => "displayp3",
+            Rgb::Rec2020 => "rec2020",
+            Rgb::AdobeRgb => "adobergb",
+            Rgb::AcesCg => "acescg",
+        })
+    }
+}
+
+impl std::fmt::Display for Illuminant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Illuminant::A => "a",
+            Illuminant::D50 => "d50",
+            Illuminant::D55 => "d55",
+            Illuminant::D60 => "d60",
+            Illuminant::D65 => "d65",
+            Illuminant::D75 => "d75",
+            Illuminant::E => "e",
+        })
+    }
+}
+
+impl std::fmt::Display for Cat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Cat::Bradford => "bradford",
+            Cat::Cat16 => "cat16",
+        })
+    }
+}
+
+/// The declaration it was parsed from
+impl std::fmt::Display for Curve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "curve {}", self.name)?;
+        if self.interp == Interp::Linear {
+            f.write_str(" linear")?;
+        }
+        for [x, y] in &self.points {
+            write!(f, " {},{}", x, y)?;
+        }
+        Ok(())
+    }
+}
+
+/// The declaration it was parsed from
+impl std::fmt::Display for Gradient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gradient {}", self.name)?;
+        for (pos, c) in &self.stops {
+            write!(f, " {},{}", pos, color::source(*c))?;
+        }
+        Ok(())
+    }
+}
+
+/// Channels print as c1 through c4 since the space isn't known,
+/// and colour literals as their values in the space they were converted to.
+impl std::fmt::Display for Obj {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&obj_source(self, None))
+    }
+}
+
+/// Jumps print the index they go to, as labels are gone once parsed.
+/// Objects print like `Obj`'s `Display`.
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&op_source(self, None))
+    }
+}
+
+/// Spaces as the names the parser reads
+#[cfg(feature = "serde")]
+mod space_serde {
+    use super::{space_source, Space};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(space: &Space, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(space_source(*space))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Space, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Space::try_from(name.as_str())
+            .map_err(|()| D::Error::custom(format!("unknown space {}", name)))
+    }
+}
+
+/// Name of `space` as the parser reads it
+fn space_source(space: Space) -> &'static str {
+    match space {
+        Space::SRGB => "srgb",
+        Space::HSV => "hsv",
+        Space::LRGB => "lrgb",
+        Space::XYZ => "xyz",
+        Space::CIELAB => "cielab",
+        Space::CIELCH => "cielch",
+        Space::OKLAB => "oklab",
+        Space::OKLCH => "oklch",
+        Space::JZAZBZ => "jzazbz",
+        Space::JZCZHZ => "jzczhz",
+    }
+}
+
+/// Source for `obj`, naming the first three channels after `space` when known.
+/// Colour literals are taken to be sRGB when it's known, as they are before `eval_colors()`.
+fn obj_source(obj: &Obj, space: Option<Space>) -> String {
+    match obj {
+        Obj::Chan(i) => match space {
+            Some(s) if *i < 3 => s.channels()[*i].to_string(),
+            _ => format!("c{}", i + 1),
+        },
+        Obj::Var(i) if *i < 9 => format!("v{}", i + 1),
+        Obj::Var(i) => format!("e{}", i - 8),
+        Obj::Num(n) => n.to_string(),
+        Obj::E => "e".to_string(),
+        Obj::Pi => "pi".to_string(),
+        Obj::Rand => "rand".to_string(),
+        Obj::Row => "row".to_string(),
+        Obj::Col => "col".to_string(),
+        Obj::Width => "width".to_string(),
+        Obj::Height => "height".to_string(),
+        Obj::XNorm => "xnorm".to_string(),
+        Obj::YNorm => "ynorm".to_string(),
+        Obj::HK2023 => "hk2023".to_string(),
+        Obj::Mask => "mask".to_string(),
+        Obj::Frame => "frame".to_string(),
+        Obj::Time => "time".to_string(),
+        Obj::Frames => "frames".to_string(),
+        Obj::InGamut => "ingamut".to_string(),
+        Obj::Color(c) => match space {
+            Some(_) => color::source(*c),
+            None => format!("[{}, {}, {}]", c[0], c[1], c[2]),
+        },
+        Obj::Delta(delta, c) => match space {
+            Some(_) => format!("{}({})", delta, color::source(*c)),
+            None => format!("{}([{}, {}, {}])", delta, c[0], c[1], c[2]),
+        },
+        Obj::DeltaVars(delta, vars) => format!(
+            "{}({})",
+            delta,
+            vars.map(|v| obj_source(&Obj::Var(v as usize), space))
+                .concat()
+        ),
+        Obj::Layer(i) => format!("l2.{}", obj_source(&Obj::Chan(*i), space)),
+    }
+}
+
+/// Source for a vector, naming channels after `space` when known
+fn vector_source(vector: &Vector, space: Option<Space>) -> String {
+    match vector {
+        Vector::All(o) => obj_source(o, space),
+        Vector::Objs(objs @ [Obj::Layer(_), ..]) => {
+            "l2.".to_string()
+                + &vector_source(
+                    &Vector::Objs(objs.map(|o| match o {
+                        Obj::Layer(i) => Obj::Chan(i),
+                        o => o,
+                    })),
+                    space,
+                )
+        }
+        // alpha has no letter
+        Vector::Objs(objs @ [Obj::Chan(_), ..])
+            if space.is_none() || objs.contains(&Obj::Chan(3)) =>
+        {
+            "c".to_string()
+                + &objs
+                    .map(|o| match o {
+                        Obj::Chan(i) => (i + 1).to_string(),
+                        _ => String::new(),
+                    })
+                    .concat()
+        }
+        Vector::Objs(objs) => objs.map(|o| obj_source(&o, space)).concat(),
+    }
+}
+
+fn op_source(operation: &Operation, space: Option<Space>) -> String {
+    let obj = |o: &Obj| obj_source(o, space);
+    let vector = |v: &Vector| vector_source(v, space);
+    match operation {
+        Operation::Space(s) => space_source(*s).to_string(),
+        Operation::Process {
+            target,
+            operation,
+            source,
+        } => format!("{} {} {}", obj(target), operation, obj(source)),
+        Operation::If {
+            left,
+            cmp,
+            right,
+            then,
+        } => format!(
+            "if {} {} {} {}",
+            obj(left),
+            cmp,
+            obj(right),
+            op_source(then, space)
+        ),
+        Operation::Goto(i) => format!("jmp {}", i),
+        Operation::GotoTmp(label) => format!("jmp {}", label),
+        Operation::Swap { t1, t2 } => format!("swap {} {}", obj(t1), obj(t2)),
+        Operation::Noise {
+            target,
+            noise,
+            x,
+            y,
+            octaves,
+            seed,
+        } => {
+            let mut s = format!("{} {} {} {}", obj(target), noise, obj(x), obj(y));
+            if *noise == Noise::Fbm {
+                s += &format!(" {}", obj(octaves));
+            }
+            if *seed != Obj::Num(0.0) {
+                s += &format!(" {}", obj(seed));
+            }
+            s
+        }
+        Operation::Gamut(g) => format!("gamut {}", g),
+        Operation::Rgb(rgb) => rgb.to_string(),
+        Operation::Adapt { from, to, cat } => match cat {
+            Cat::Bradford => format!("adapt {} {}", from, to),
+            Cat::Cat16 => format!("adapt {} {} {}", from, to, cat),
+        },
+        Operation::Temperature(kelvin) => format!("temperature {}", obj(kelvin)),
+        Operation::Tint(tint) => format!("tint {}", obj(tint)),
+        Operation::WhitepointFrom { r, g, b } => {
+            format!("whitepoint_from {} {} {}", obj(r), obj(g), obj(b))
+        }
+        Operation::Vector {
+            target,
+            operation,
+            source,
+        } => {
+            let target = vector(&Vector::Objs(*target));
+            match operation {
+                VecOp::Op(op) => format!("{} {} {}", target, op, vector(source)),
+                VecOp::Mix(amount) => {
+                    format!("{} mix {} {}", target, vector(source), obj(amount))
+                }
+                VecOp::Cross => format!("{} cross {}", target, vector(source)),
+            }
+        }
+        Operation::Dot { target, a, b } => {
+            format!("{} dot {} {}", obj(target), vector(a), vector(b))
+        }
+        Operation::Length { target, source } => {
+            format!("{} length {}", obj(target), vector(source))
+        }
+        Operation::Blend { mode, opacity } => match opacity {
+            Obj::Num(n) if *n == 1.0 => format!("blend {}", mode),
+            _ => format!("blend {} {}", mode, obj(opacity)),
+        },
+        Operation::Curve {
+            target,
+            operation,
+            curve,
+            source,
+        } => format!(
+            "{} {} {}({})",
+            obj(target),
+            operation,
+            curve.name,
+            obj(source)
+        ),
+        Operation::Gradient {
+            target,
+            operation,
+            gradient,
+            source,
+        } => format!(
+            "{} {} {}({})",
+            vector(&Vector::Objs(*target)),
+            operation,
+            gradient.name,
+            obj(source)
+        ),
+    }
+}
+
+// }}}
+
 fn tar(item: &str, space: Space) -> Result<Obj, ()> {
-    match item.as_ref() {
+    match item {
         // don't hate I made these with a vim macro
         "c1" => Ok(Obj::Chan(0)),
         "c2" => Ok(Obj::Chan(1)),
@@ -162,7 +778,7 @@ fn tar(item: &str, space: Space) -> Result<Obj, ()> {
 }
 
 fn src(item: &str, space: Space) -> Result<Obj, ()> {
-    match item.as_ref() {
+    match item {
         "e" => Ok(Obj::E),
         "pi" => Ok(Obj::Pi),
         "rand" => Ok(Obj::Rand),
@@ -173,15 +789,61 @@ fn src(item: &str, space: Space) -> Result<Obj, ()> {
         "xnorm" => Ok(Obj::XNorm),
         "ynorm" => Ok(Obj::YNorm),
         "hk2023" => Ok(Obj::HK2023),
+        "mask" => Ok(Obj::Mask),
+        "frame" => Ok(Obj::Frame),
+        "time" => Ok(Obj::Time),
+        "frames" => Ok(Obj::Frames),
+        "ingamut" => Ok(Obj::InGamut),
         val => match val.parse::<f32>() {
             Ok(f) => Ok(Obj::Num(f)),
-            Err(_) => tar(val, space),
+            Err(_) => tar(val, space)
+                .or_else(|()| delta(val))
+                .or_else(|()| layer(val, space)),
         },
     }
 }
 
+/// Three variables written together like `v1v2v3`
+fn vars(item: &str) -> Result<[usize; 3], ()> {
+    if item.len() != 6 || !item.is_ascii() {
+        return Err(());
+    }
+    let var = |n: usize| match tar(&item[n * 2..n * 2 + 2], Space::SRGB) {
+        Ok(Obj::Var(i)) => Ok(i),
+        _ => Err(()),
+    };
+    Ok([var(0)?, var(1)?, var(2)?])
+}
+
+/// A channel of the second layer like `l2.r` or `l2.c4`
+fn layer(item: &str, space: Space) -> Result<Obj, ()> {
+    match tar(item.strip_prefix("l2.").ok_or(())?, space)? {
+        Obj::Chan(i) => Ok(Obj::Layer(i)),
+        _ => Err(()),
+    }
+}
+
+/// A colour difference like `deltaok(#ff8800)` or `deltae2000(v1v2v3)`
+fn delta(item: &str) -> Result<Obj, ()> {
+    let (name, arg) = item
+        .strip_suffix(')')
+        .and_then(|i| i.split_once('('))
+        .ok_or(())?;
+    let delta = match name {
+        "deltae76" => Delta::E76,
+        "deltae2000" => Delta::E2000,
+        "deltaok" => Delta::Ok,
+        _ => return Err(()),
+    };
+    let arg = arg.trim();
+    if let Ok(v) = vars(arg) {
+        return Ok(Obj::DeltaVars(delta, v.map(|i| i as u8)));
+    }
+    color::parse(arg).map(|c| Obj::Delta(delta, c)).ok_or(())
+}
+
 fn op(item: &str) -> Result<Op, ()> {
-    match item.as_ref() {
+    match item {
         // Base
         "+=" | "+" | "add" => Ok(Op::Add),
         "-=" | "-" | "sub" => Ok(Op::Sub),
@@ -237,7 +899,7 @@ fn op(item: &str) -> Result<Op, ()> {
 }
 
 fn cmp(item: &str) -> Result<Cmp, ()> {
-    match item.as_ref() {
+    match item {
         "==" | "eq" => Ok(Cmp::Eq),
         "!=" | "!" | "neq" => Ok(Cmp::NEq),
         ">" | "gt" => Ok(Cmp::Gt),
@@ -248,18 +910,98 @@ fn cmp(item: &str) -> Result<Cmp, ()> {
     }
 }
 
+fn noise(item: &str) -> Result<Noise, ()> {
+    match item {
+        "value" => Ok(Noise::Value),
+        "perlin" => Ok(Noise::Perlin),
+        "simplex" => Ok(Noise::Simplex),
+        "worley" => Ok(Noise::Worley),
+        "fbm" => Ok(Noise::Fbm),
+        _ => Err(()),
+    }
+}
+
+fn gamut(item: &str) -> Result<Gamut, ()> {
+    match item {
+        "clip" => Ok(Gamut::Clip),
+        "chroma" => Ok(Gamut::Chroma),
+        "project" => Ok(Gamut::Project),
+        _ => Err(()),
+    }
+}
+
+fn blend(item: &str) -> Result<Blend, ()> {
+    match item {
+        "normal" => Ok(Blend::Normal),
+        "multiply" => Ok(Blend::Multiply),
+        "screen" => Ok(Blend::Screen),
+        "overlay" => Ok(Blend::Overlay),
+        "softlight" => Ok(Blend::SoftLight),
+        "hardlight" => Ok(Blend::HardLight),
+        "darken" => Ok(Blend::Darken),
+        "lighten" => Ok(Blend::Lighten),
+        "difference" => Ok(Blend::Difference),
+        "exclusion" => Ok(Blend::Exclusion),
+        "add" => Ok(Blend::Add),
+        "subtract" => Ok(Blend::Subtract),
+        "colordodge" => Ok(Blend::ColorDodge),
+        "colorburn" => Ok(Blend::ColorBurn),
+        "hue" => Ok(Blend::Hue),
+        "saturation" => Ok(Blend::Saturation),
+        "color" | "colour" => Ok(Blend::Color),
+        "luminosity" => Ok(Blend::Luminosity),
+        _ => Err(()),
+    }
+}
+
 fn spc(item: &str) -> Result<Space, ()> {
-    Space::try_from(item.as_ref())
+    Space::try_from(item)
+}
+
+fn rgb(item: &str) -> Result<Rgb, ()> {
+    match item {
+        "displayp3" | "p3" => Ok(Rgb::DisplayP3),
+        "rec2020" | "bt2020" => Ok(Rgb::Rec2020),
+        "adobergb" => Ok(Rgb::AdobeRgb),
+        "acescg" => Ok(Rgb::AcesCg),
+        _ => Err(()),
+    }
+}
+
+fn illuminant(item: &str) -> Result<Illuminant, ()> {
+    match item {
+        "a" => Ok(Illuminant::A),
+        "d50" => Ok(Illuminant::D50),
+        "d55" => Ok(Illuminant::D55),
+        "d60" => Ok(Illuminant::D60),
+        "d65" => Ok(Illuminant::D65),
+        "d75" => Ok(Illuminant::D75),
+        "e" => Ok(Illuminant::E),
+        _ => Err(()),
+    }
+}
+
+fn cat(item: &str) -> Result<Cat, ()> {
+    match item {
+        "bradford" => Ok(Cat::Bradford),
+        "cat16" => Ok(Cat::Cat16),
+        _ => Err(()),
+    }
 }
 
 fn oper_space(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
-    if items.as_ref().len() == 1 {
-        match spc(items[0]) {
-            Ok(s) => {
+    if items.len() == 1 {
+        match (spc(items[0]), rgb(items[0])) {
+            (Ok(s), _) => {
                 *space = s;
                 Ok(Operation::Space(s))
             }
-            Err(()) => Err(OpError::Partial {
+            // channels are named like linear RGB
+            (_, Ok(rgb)) => {
+                *space = Space::LRGB;
+                Ok(Operation::Rgb(rgb))
+            }
+            _ => Err(OpError::Partial {
                 line,
                 details: "Invalid space change".to_string(),
             }),
@@ -269,30 +1011,361 @@ fn oper_space(items: &[&str], space: &mut Space, line: usize) -> Result<Operatio
     }
 }
 
+/// A vector target like `rgb`, `c123`, or `v1v2v3`.
+/// Channels are letters of the current space, all three different
+fn vector_target(item: &str, space: Space) -> Result<[Obj; 3], ()> {
+    // 'chl' in an lch space is channels, not a c123 list
+    let digits = item
+        .strip_prefix('c')
+        .filter(|d| d.chars().all(|c| c.is_ascii_digit()));
+    let chans: Option<Vec<Obj>> = if let Some(digits) = digits {
+        digits
+            .chars()
+            .map(|c| match c {
+                '1'..='4' => Some(Obj::Chan(c as usize - '1' as usize)),
+                _ => None,
+            })
+            .collect()
+    } else {
+        item.chars()
+            .map(|c| {
+                let n = space.channels().iter().position(|ch| *ch == c)?;
+                Some(Obj::Chan(n))
+            })
+            .collect()
+    };
+    let objs: [Obj; 3] = match chans.and_then(|c| c.try_into().ok()) {
+        Some(objs) => objs,
+        None => vars(item)?.map(Obj::Var),
+    };
+    if objs[0] == objs[1] || objs[1] == objs[2] || objs[0] == objs[2] {
+        return Err(());
+    }
+    Ok(objs)
+}
+
+/// A vector source: a vector target, colour literal, or any source for all three
+fn vector_src(item: &str, space: Space) -> Result<Vector, ()> {
+    if let Ok(objs) = vector_target(item, space) {
+        return Ok(Vector::Objs(objs));
+    }
+    if let Some(Ok(objs @ [Obj::Chan(_), ..])) =
+        item.strip_prefix("l2.").map(|i| vector_target(i, space))
+    {
+        return Ok(Vector::Objs(objs.map(|o| match o {
+            Obj::Chan(i) => Obj::Layer(i),
+            o => o,
+        })));
+    }
+    match src(item, space) {
+        Ok(o) => Ok(Vector::All(o)),
+        Err(()) => color::parse(item)
+            .map(|c| Vector::All(Obj::Color(c)))
+            .ok_or(()),
+    }
+}
+
+/// Whether `item` is written like a vector, even if it isn't valid in `space`
+fn vector_like(item: &str) -> bool {
+    let channel = |c: char| Space::ALL.iter().any(|s| s.channels().contains(&c));
+    item.len() == 3 && item.chars().all(channel)
+        || item.len() == 4 && item.starts_with('c') && item[1..].chars().all(|c| c.is_ascii_digit())
+        || vars(item).is_ok()
+}
+
+fn oper_vector(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
+    let partial = |details: &str| {
+        Err(OpError::Partial {
+            line,
+            details: details.to_string(),
+        })
+    };
+    let invalid_source = || partial("Invalid source");
+    match items {
+        [target, "dot", a, b] => match (
+            tar(target, *space),
+            vector_src(a, *space),
+            vector_src(b, *space),
+        ) {
+            (Err(()), ..) => partial("Invalid target"),
+            (Ok(target), Ok(a), Ok(b)) => Ok(Operation::Dot { target, a, b }),
+            _ => invalid_source(),
+        },
+        [target, "length", source] => match (tar(target, *space), vector_src(source, *space)) {
+            (Err(()), _) => partial("Invalid target"),
+            (Ok(target), Ok(source)) => Ok(Operation::Length { target, source }),
+            _ => invalid_source(),
+        },
+        [_, "dot", ..] => partial("dot takes two vectors"),
+        [_, "length", ..] => partial("length takes one vector"),
+        [target, operation, args @ ..] if vector_like(target) => {
+            let Ok(target) = vector_target(target, *space) else {
+                return partial(&format!(
+                    "Vectors are three different channels of {} like '{}', c123, or v1v2v3",
+                    space_source(*space),
+                    space.channels().iter().collect::<String>()
+                ));
+            };
+            let operation = match (*operation, args.len()) {
+                ("mix", 2) => match src(args[1], *space) {
+                    Ok(amount) => VecOp::Mix(amount),
+                    Err(()) => return invalid_source(),
+                },
+                ("mix", _) => return partial("mix takes a source and an amount"),
+                ("cross", 1) => VecOp::Cross,
+                (o, 1) => match op(o) {
+                    Ok(o) => VecOp::Op(o),
+                    Err(()) => return partial("Invalid operator"),
+                },
+                _ => return Err(OpError::Unknown { line }),
+            };
+            match vector_src(args[0], *space) {
+                Ok(source) => Ok(Operation::Vector {
+                    target,
+                    operation,
+                    source,
+                }),
+                Err(()) => invalid_source(),
+            }
+        }
+        _ => Err(OpError::Unknown { line }),
+    }
+}
+
+/// Whether `name` can be declared as a curve or gradient.
+/// Names of functions that take parentheses are taken
+fn table_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !matches!(name, "rgb" | "deltae76" | "deltae2000" | "deltaok")
+}
+
+/// A `curve` or `gradient` declaration, or None if `items` isn't one
+fn declaration(items: &[&str], line: usize) -> Option<Result<Table, OpError>> {
+    let partial = |details: String| Some(Err(OpError::Partial { line, details }));
+    let kind = *items.first()?;
+    if kind != "curve" && kind != "gradient" {
+        return None;
+    }
+    let name = match items.get(1) {
+        Some(n) if table_name(n) => n.to_string(),
+        _ => {
+            return partial(format!(
+                "{} names are letters, digits, and '_', starting with a letter",
+                kind
+            ))
+        }
+    };
+    let mut args = &items[2..];
+
+    if kind == "curve" {
+        let interp = match args.first() {
+            Some(&"linear") => Some(Interp::Linear),
+            Some(&"cubic") => Some(Interp::Cubic),
+            Some(word) if word.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                return partial(format!(
+                    "Unknown interpolation '{}', use linear or cubic",
+                    word
+                ))
+            }
+            _ => None,
+        };
+        if interp.is_some() {
+            args = &args[1..];
+        }
+        let interp = interp.unwrap_or(Interp::Cubic);
+        let mut points = Vec::new();
+        for arg in args {
+            match arg
+                .split_once(',')
+                .map(|(x, y)| (x.parse::<f32>(), y.parse::<f32>()))
+            {
+                Some((Ok(x), Ok(y))) if x.is_finite() && y.is_finite() => points.push([x, y]),
+                _ => return partial(format!("Invalid curve point '{}', expected 'x,y'", arg)),
+            }
+        }
+        if points.len() < 2 {
+            return partial("Curves need at least two points like '0,0 1,1'".to_string());
+        }
+        if points.windows(2).any(|p| p[1][0] <= p[0][0]) {
+            return partial("Curve points need increasing x".to_string());
+        }
+        Some(Ok(Table::Curve(Curve::new(name, interp, points))))
+    } else {
+        let mut stops = Vec::new();
+        for arg in args {
+            match arg
+                .split_once(',')
+                .map(|(p, c)| (p.parse::<f32>(), color::parse(c.trim())))
+            {
+                Some((Ok(p), Some(c))) if p.is_finite() => stops.push((p, c)),
+                _ => {
+                    return partial(format!(
+                        "Invalid gradient stop '{}', expected 'position,colour'",
+                        arg
+                    ))
+                }
+            }
+        }
+        if stops.len() < 2 {
+            return partial("Gradients need at least two stops like '0,black 1,white'".to_string());
+        }
+        if stops.windows(2).any(|s| s[1].0 < s[0].0) {
+            return partial("Gradient stops can't go back in position".to_string());
+        }
+        Some(Ok(Table::Gradient(Gradient::new(name, stops))))
+    }
+}
+
+/// A lookup like `v1 = tone(l)` or `rgb = gmap(l)`.
+/// The table is left empty for `link_table()` to fill in
+fn oper_lookup(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
+    let partial = |details: &str| {
+        Err(OpError::Partial {
+            line,
+            details: details.to_string(),
+        })
+    };
+    let [target, operation, call] = items else {
+        return Err(OpError::Unknown { line });
+    };
+    let Some((name, arg)) = call.strip_suffix(')').and_then(|c| c.split_once('(')) else {
+        return Err(OpError::Unknown { line });
+    };
+    if !table_name(name) {
+        return Err(OpError::Unknown { line });
+    }
+    let Ok(operation) = op(operation) else {
+        return partial("Invalid operator");
+    };
+    let Ok(source) = src(arg.trim(), *space) else {
+        return partial("Invalid source");
+    };
+    let name = name.to_string();
+    if vector_like(target) {
+        match vector_target(target, *space) {
+            Ok(target) => Ok(Operation::Gradient {
+                target,
+                operation,
+                gradient: Box::new(Gradient {
+                    name,
+                    stops: Vec::new(),
+                    table: Vec::new(),
+                }),
+                source,
+            }),
+            Err(()) => partial("Invalid target"),
+        }
+    } else {
+        match tar(target, *space) {
+            Ok(target) => Ok(Operation::Curve {
+                target,
+                operation,
+                curve: Box::new(Curve {
+                    name,
+                    interp: Interp::Linear,
+                    points: Vec::new(),
+                    table: Vec::new(),
+                }),
+                source,
+            }),
+            Err(()) => partial("Invalid target"),
+        }
+    }
+}
+
+/// Adds a declaration to `tables`, unless its name is taken
+fn declare(
+    tables: &mut HashMap<String, Table>,
+    table: Table,
+    line: usize,
+) -> Result<String, OpError> {
+    let source = match &table {
+        Table::Curve(c) => c.to_string(),
+        Table::Gradient(g) => g.to_string(),
+    };
+    let name = match &table {
+        Table::Curve(c) => &c.name,
+        Table::Gradient(g) => &g.name,
+    };
+    if tables.contains_key(name) {
+        return Err(OpError::Partial {
+            line,
+            details: format!("'{}' is already declared", name),
+        });
+    }
+    tables.insert(name.clone(), table);
+    Ok(source)
+}
+
+/// Fills in the table of a lookup from the declaration it names
+fn link_table(
+    operation: &mut Operation,
+    tables: &HashMap<String, Table>,
+    line: usize,
+) -> Result<(), OpError> {
+    let (name, found) = match operation {
+        Operation::If { then, .. } => return link_table(then, tables, line),
+        Operation::Curve { curve, .. } => match tables.get(&curve.name) {
+            Some(Table::Curve(c)) => {
+                **curve = c.clone();
+                return Ok(());
+            }
+            found => (&curve.name, found),
+        },
+        Operation::Gradient { gradient, .. } => match tables.get(&gradient.name) {
+            Some(Table::Gradient(g)) => {
+                **gradient = g.clone();
+                return Ok(());
+            }
+            found => (&gradient.name, found),
+        },
+        _ => return Ok(()),
+    };
+    let details = match found {
+        Some(Table::Curve(_)) => format!(
+            "'{}' is a curve, which looks up into one target like 'v1'",
+            name
+        ),
+        Some(Table::Gradient(_)) => format!(
+            "'{}' is a gradient, which looks up into a vector like 'rgb'",
+            name
+        ),
+        None => format!("No curve or gradient named '{}' is declared above", name),
+    };
+    Err(OpError::Partial { line, details })
+}
+
 fn oper_process(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
     if items.len() == 3 {
-        let parsed = (tar(items[0], *space), op(items[1]), src(items[2], *space));
-        if parsed.0.is_err() {
-            Err(OpError::Partial {
+        let parsed = (
+            tar(items[0], *space),
+            op(items[1]),
+            src(items[2], *space).or_else(|()| color::parse(items[2]).map(Obj::Color).ok_or(())),
+        );
+        match parsed {
+            (Err(()), _, _) => Err(OpError::Partial {
                 line,
                 details: "Invalid target".to_string(),
-            })
-        } else if parsed.1.is_err() {
-            Err(OpError::Partial {
+            }),
+            (_, Err(()), _) => Err(OpError::Partial {
                 line,
                 details: "Invalid operator".to_string(),
-            })
-        } else if parsed.2.is_err() {
-            Err(OpError::Partial {
+            }),
+            (_, _, Err(())) => Err(OpError::Partial {
                 line,
                 details: "Invalid source".to_string(),
-            })
-        } else {
-            Ok(Operation::Process {
-                target: parsed.0.unwrap(),
-                operation: parsed.1.unwrap(),
-                source: parsed.2.unwrap(),
-            })
+            }),
+            (Ok(Obj::Var(_) | Obj::Chan(3)), _, Ok(Obj::Color(_))) => Err(OpError::Partial {
+                line,
+                details: "Colour literals need a colour channel target".to_string(),
+            }),
+            (Ok(target), Ok(operation), Ok(source)) => Ok(Operation::Process {
+                target,
+                operation,
+                source,
+            }),
         }
     } else {
         Err(OpError::Unknown { line })
@@ -305,28 +1378,25 @@ fn oper_if(items: &[&str], space: &mut Space, line: usize) -> Result<Operation,
             return Err(OpError::Unknown { line });
         }
         let parsed = (src(items[1], *space), cmp(items[2]), src(items[3], *space));
-        if parsed.0.is_err() {
-            Err(OpError::Partial {
+        match parsed {
+            (Err(()), _, _) => Err(OpError::Partial {
                 line,
                 details: "Invalid target".to_string(),
-            })
-        } else if parsed.1.is_err() {
-            Err(OpError::Partial {
+            }),
+            (_, Err(()), _) => Err(OpError::Partial {
                 line,
                 details: "Invalid operator".to_string(),
-            })
-        } else if parsed.2.is_err() {
-            Err(OpError::Partial {
+            }),
+            (_, _, Err(())) => Err(OpError::Partial {
                 line,
                 details: "Invalid source".to_string(),
-            })
-        } else {
-            Ok(Operation::If {
-                left: parsed.0.unwrap(),
-                cmp: parsed.1.unwrap(),
-                right: parsed.2.unwrap(),
+            }),
+            (Ok(left), Ok(cmp), Ok(right)) => Ok(Operation::If {
+                left,
+                cmp,
+                right,
                 then: Box::new(parse_op(&items[4..], space, line)?),
-            })
+            }),
         }
     } else {
         Err(OpError::Unknown { line })
@@ -349,21 +1419,16 @@ fn oper_swap(items: &[&str], space: &mut Space, line: usize) -> Result<Operation
     if items.len() == 3 {
         if items[0] == "swap" {
             let parsed = (tar(items[1], *space), tar(items[2], *space));
-            if parsed.0.is_err() {
-                Err(OpError::Partial {
+            match parsed {
+                (Err(()), _) => Err(OpError::Partial {
                     line,
                     details: "Invalid left target".to_string(),
-                })
-            } else if parsed.1.is_err() {
-                Err(OpError::Partial {
+                }),
+                (_, Err(())) => Err(OpError::Partial {
                     line,
                     details: "Invalid right target".to_string(),
-                })
-            } else {
-                Ok(Operation::Swap {
-                    t1: parsed.0.unwrap(),
-                    t2: parsed.1.unwrap(),
-                })
+                }),
+                (Ok(t1), Ok(t2)) => Ok(Operation::Swap { t1, t2 }),
             }
         } else {
             Err(OpError::Unknown { line })
@@ -373,10 +1438,172 @@ fn oper_swap(items: &[&str], space: &mut Space, line: usize) -> Result<Operation
     }
 }
 
+fn oper_gamut(items: &[&str], _space: &mut Space, line: usize) -> Result<Operation, OpError> {
+    if items.first() != Some(&"gamut") {
+        return Err(OpError::Unknown { line });
+    }
+    match items.get(1).map(|i| gamut(i)) {
+        Some(Ok(g)) if items.len() == 2 => Ok(Operation::Gamut(g)),
+        _ => Err(OpError::Partial {
+            line,
+            details: "Gamut mapping is 'clip', 'chroma', or 'project'".to_string(),
+        }),
+    }
+}
+
+fn oper_blend(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
+    if items.first() != Some(&"blend") {
+        return Err(OpError::Unknown { line });
+    }
+    let parsed = (
+        items.get(1).map(|i| blend(i)),
+        items.get(2).map_or(Ok(Obj::Num(1.0)), |i| src(i, *space)),
+    );
+    match parsed {
+        _ if items.len() > 3 => Err(OpError::Partial {
+            line,
+            details: "blend takes a mode and an optional opacity".to_string(),
+        }),
+        (Some(Ok(mode)), Ok(opacity)) => Ok(Operation::Blend { mode, opacity }),
+        (Some(Ok(_)), Err(())) => Err(OpError::Partial {
+            line,
+            details: "Invalid source".to_string(),
+        }),
+        _ => Err(OpError::Partial {
+            line,
+            details: "Blend modes are 'normal', 'multiply', 'screen', 'overlay', 'softlight', \
+                'hardlight', 'darken', 'lighten', 'difference', 'exclusion', 'add', 'subtract', \
+                'colordodge', 'colorburn', 'hue', 'saturation', 'color', or 'luminosity'"
+                .to_string(),
+        }),
+    }
+}
+
+fn oper_adapt(items: &[&str], _space: &mut Space, line: usize) -> Result<Operation, OpError> {
+    if items.first() != Some(&"adapt") {
+        return Err(OpError::Unknown { line });
+    }
+    let parsed = (
+        items.get(1).map(|i| illuminant(i)),
+        items.get(2).map(|i| illuminant(i)),
+        items.get(3).map_or(Ok(Cat::Bradford), |i| cat(i)),
+    );
+    match parsed {
+        _ if items.len() > 4 => Err(OpError::Partial {
+            line,
+            details: "adapt takes two white points and an optional model".to_string(),
+        }),
+        (Some(Ok(from)), Some(Ok(to)), Ok(cat)) => Ok(Operation::Adapt { from, to, cat }),
+        (Some(Ok(_)), Some(Ok(_)), Err(())) => Err(OpError::Partial {
+            line,
+            details: "Adaptation model is 'bradford' or 'cat16'".to_string(),
+        }),
+        _ => Err(OpError::Partial {
+            line,
+            details: "White points are 'a', 'd50', 'd55', 'd60', 'd65', 'd75', or 'e'".to_string(),
+        }),
+    }
+}
+
+fn oper_white(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
+    let args: Result<Vec<Obj>, ()> = items.iter().skip(1).map(|i| src(i, *space)).collect();
+    let (name, wanted) = match items.first() {
+        Some(&"temperature") => ("temperature", 1),
+        Some(&"tint") => ("tint", 1),
+        Some(&"whitepoint_from") => ("whitepoint_from", 3),
+        _ => return Err(OpError::Unknown { line }),
+    };
+    match args {
+        Ok(args) if args.len() == wanted => Ok(match name {
+            "temperature" => Operation::Temperature(args[0]),
+            "tint" => Operation::Tint(args[0]),
+            _ => Operation::WhitepointFrom {
+                r: args[0],
+                g: args[1],
+                b: args[2],
+            },
+        }),
+        Ok(_) if wanted == 1 => Err(OpError::Partial {
+            line,
+            details: format!("{} takes one source", name),
+        }),
+        Ok(_) => Err(OpError::Partial {
+            line,
+            details: format!("{} takes an sRGB colour as three sources", name),
+        }),
+        Err(()) => Err(OpError::Partial {
+            line,
+            details: "Invalid source".to_string(),
+        }),
+    }
+}
+
+fn oper_noise(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
+    let kind = match items.get(1).map(|i| noise(i)) {
+        Some(Ok(n)) => n,
+        _ => return Err(OpError::Unknown { line }),
+    };
+    let args = &items[2..];
+    let (octaves, seed) = match (kind, args.len()) {
+        (Noise::Fbm, 3 | 4) => (Some(args[2]), args.get(3)),
+        (Noise::Fbm, _) => {
+            return Err(OpError::Partial {
+                line,
+                details: "fbm takes x, y, octaves, and an optional seed".to_string(),
+            })
+        }
+        (_, 2 | 3) => (None, args.get(2)),
+        _ => {
+            return Err(OpError::Partial {
+                line,
+                details: "Noise takes x, y, and an optional seed".to_string(),
+            })
+        }
+    };
+    let parsed = (
+        tar(items[0], *space),
+        src(args[0], *space),
+        src(args[1], *space),
+        octaves.map_or(Ok(Obj::Num(1.0)), |o| src(o, *space)),
+        seed.map_or(Ok(Obj::Num(0.0)), |s| src(s, *space)),
+    );
+    match parsed {
+        (Err(()), ..) => Err(OpError::Partial {
+            line,
+            details: "Invalid target".to_string(),
+        }),
+        (Ok(target), Ok(x), Ok(y), Ok(octaves), Ok(seed)) => Ok(Operation::Noise {
+            target,
+            noise: kind,
+            x,
+            y,
+            octaves,
+            seed,
+        }),
+        _ => Err(OpError::Partial {
+            line,
+            details: "Invalid source".to_string(),
+        }),
+    }
+}
+
 fn parse_op(items: &[&str], space: &mut Space, line: usize) -> Result<Operation, OpError> {
-    let mut results = [oper_process, oper_space, oper_if, oper_jmp, oper_swap]
-        .iter()
-        .map(|f| f(&items, space, line));
+    let mut results = [
+        oper_lookup,
+        oper_blend,
+        oper_adapt,
+        oper_white,
+        oper_vector,
+        oper_noise,
+        oper_process,
+        oper_space,
+        oper_if,
+        oper_jmp,
+        oper_swap,
+        oper_gamut,
+    ]
+    .iter()
+    .map(|f| f(items, space, line));
 
     let mut non_unknown = None;
     loop {
@@ -384,7 +1611,7 @@ fn parse_op(items: &[&str], space: &mut Space, line: usize) -> Result<Operation,
             Some(i) => match i {
                 Ok(o) => break Ok(o),
                 Err(e) => {
-                    if non_unknown == None
+                    if non_unknown.is_none()
                         && match e {
                             OpError::Partial { .. } => true,
                             OpError::Unknown { .. } => false,
@@ -404,6 +1631,133 @@ fn parse_op(items: &[&str], space: &mut Space, line: usize) -> Result<Operation,
     }
 }
 
+/// Splits `row` on whitespace, keeping anything in parentheses together like `rgb(1, 0.5, 0)`
+fn split_items(row: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = None;
+    let mut depth = 0_usize;
+    for (i, c) in row.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            _ if c.is_ascii_whitespace() && depth == 0 => {
+                if let Some(s) = start.take() {
+                    items.push(&row[s..i])
+                }
+                continue;
+            }
+            _ => (),
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(s) = start {
+        items.push(&row[s..])
+    }
+    items
+}
+
+fn vector_objs_mut(vector: &mut Vector) -> Vec<&mut Obj> {
+    match vector {
+        Vector::Objs(objs) => objs.iter_mut().collect(),
+        Vector::All(o) => vec![o],
+    }
+}
+
+/// Every object in `operation`, including inside an `if`
+fn objs_mut(operation: &mut Operation) -> Vec<&mut Obj> {
+    match operation {
+        Operation::Process { target, source, .. } => vec![target, source],
+        Operation::If {
+            left, right, then, ..
+        } => {
+            let mut objs = objs_mut(then);
+            objs.extend([left, right]);
+            objs
+        }
+        Operation::Swap { t1, t2 } => vec![t1, t2],
+        Operation::Noise {
+            target,
+            x,
+            y,
+            octaves,
+            seed,
+            ..
+        } => vec![target, x, y, octaves, seed],
+        Operation::Temperature(source) | Operation::Tint(source) => vec![source],
+        Operation::WhitepointFrom { r, g, b } => vec![r, g, b],
+        Operation::Vector {
+            target,
+            operation,
+            source,
+        } => {
+            let mut objs: Vec<&mut Obj> = target.iter_mut().collect();
+            objs.extend(vector_objs_mut(source));
+            if let VecOp::Mix(amount) = operation {
+                objs.push(amount)
+            }
+            objs
+        }
+        Operation::Dot { target, a, b } => {
+            let mut objs = vec![target];
+            objs.extend(vector_objs_mut(a));
+            objs.extend(vector_objs_mut(b));
+            objs
+        }
+        Operation::Length { target, source } => {
+            let mut objs = vec![target];
+            objs.extend(vector_objs_mut(source));
+            objs
+        }
+        Operation::Blend { opacity, .. } => vec![opacity],
+        Operation::Curve { target, source, .. } => vec![target, source],
+        Operation::Gradient { target, source, .. } => {
+            let mut objs: Vec<&mut Obj> = target.iter_mut().collect();
+            objs.push(source);
+            objs
+        }
+        Operation::Space(_)
+        | Operation::Goto(_)
+        | Operation::GotoTmp(_)
+        | Operation::Gamut(_)
+        | Operation::Rgb(_)
+        | Operation::Adapt { .. } => vec![],
+    }
+}
+
+/// Converts colour literals and gradient tables from sRGB into the space they're used in,
+/// which for differences is the space they compare in
+fn eval_colors(operations: &mut [Operation]) {
+    let (mut space, mut rgb) = (Space::SRGB, None);
+    for operation in operations.iter_mut() {
+        match operation {
+            Operation::Space(s) => (space, rgb) = (*s, None),
+            Operation::Rgb(r) => (space, rgb) = (Space::XYZ, Some(*r)),
+            _ => (),
+        }
+        let convert = |c: &mut [f32; 3]| match rgb {
+            Some(r) => {
+                convert_space(Space::SRGB, Space::XYZ, c);
+                r.from_xyz(c);
+            }
+            None => convert_space(Space::SRGB, space, c),
+        };
+        let mut inner = &mut *operation;
+        while let Operation::If { then, .. } = inner {
+            inner = then
+        }
+        if let Operation::Gradient { gradient, .. } = inner {
+            gradient.table.iter_mut().for_each(convert)
+        }
+        for obj in objs_mut(operation) {
+            match obj {
+                Obj::Color(c) => convert(c),
+                Obj::Delta(delta, c) => convert_space(Space::SRGB, delta.space(), c),
+                _ => (),
+            }
+        }
+    }
+}
+
 fn goto_conv(op: Option<Operation>, labels: &HashMap<String, usize>) -> Option<Operation> {
     match op {
         Some(Operation::GotoTmp(s)) => labels.get(&s).map(|i| Operation::Goto(*i)),
@@ -422,12 +1776,350 @@ fn goto_conv(op: Option<Operation>, labels: &HashMap<String, usize>) -> Option<O
     }
 }
 
-pub fn parse_ops<S: AsRef<str>>(code: S, mut space: Space) -> (Vec<Operation>, Vec<OpError>) {
+/// Finds jumps backwards that aren't inside an `if`
+/// and have no conditional jump out of the code they repeat.
+/// Those loop on every pixel until the jump limit.
+fn runaway_loops(
+    operations: &[Operation],
+    lines: &[usize],
+    labels: &HashMap<String, usize>,
+) -> Vec<Warning> {
+    let exits = |from: usize, to: usize| {
+        operations[from..to].iter().any(|o| match o {
+            Operation::If { then, .. } => match then.as_ref() {
+                Operation::GotoTmp(l) => labels.get(l).is_some_and(|i| *i < from || *i > to),
+                _ => false,
+            },
+            _ => false,
+        })
+    };
+    operations
+        .iter()
+        .enumerate()
+        .filter_map(|(n, o)| match o {
+            Operation::GotoTmp(l) => match labels.get(l) {
+                Some(i) if *i <= n && !exits(*i, n) => Some(Warning {
+                    line: lines[n],
+                    kind: Lint::Runaway,
+                    details: format!("Jump back to '{}' always loops until the jump limit", l),
+                }),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `op` takes the target as its first value, like `+`
+fn reads_target(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Add
+            | Op::Sub
+            | Op::Mul
+            | Op::Div
+            | Op::Mod
+            | Op::Pow
+            | Op::Atan2
+            | Op::Copysign
+            | Op::Diveuclid
+            | Op::Hypot
+            | Op::Log
+            | Op::Max
+            | Op::Min
+            | Op::Remeuclid
+            | Op::Invert
+    )
+}
+
+fn vector_objs(vector: &Vector) -> Vec<Obj> {
+    match vector {
+        Vector::Objs(objs) => objs.to_vec(),
+        Vector::All(o) => vec![*o],
+    }
+}
+
+/// Variables and channels an operation reads and writes, including inside an `if`
+pub(super) fn accesses(operation: &Operation) -> (Vec<Obj>, Vec<Obj>) {
+    let (reads, writes) = match operation {
+        Operation::Process {
+            target,
+            operation,
+            source,
+        } => {
+            let mut reads = vec![*source];
+            if reads_target(operation) {
+                reads.push(*target)
+            }
+            (reads, vec![*target])
+        }
+        Operation::If {
+            left, right, then, ..
+        } => {
+            let (mut reads, writes) = accesses(then);
+            reads.extend([*left, *right]);
+            (reads, writes)
+        }
+        Operation::Swap { t1, t2 } => (vec![*t1, *t2], vec![*t1, *t2]),
+        Operation::Noise {
+            target,
+            x,
+            y,
+            octaves,
+            seed,
+            ..
+        } => (vec![*x, *y, *octaves, *seed], vec![*target]),
+        Operation::Temperature(source) | Operation::Tint(source) => (vec![*source], vec![]),
+        Operation::WhitepointFrom { r, g, b } => (vec![*r, *g, *b], vec![]),
+        Operation::Vector {
+            target,
+            operation,
+            source,
+        } => {
+            let mut reads = vector_objs(source);
+            match operation {
+                VecOp::Op(op) if !reads_target(op) => (),
+                VecOp::Mix(amount) => {
+                    reads.push(*amount);
+                    reads.extend(target)
+                }
+                _ => reads.extend(target),
+            }
+            (reads, target.to_vec())
+        }
+        Operation::Dot { target, a, b } => {
+            let mut reads = vector_objs(a);
+            reads.extend(vector_objs(b));
+            (reads, vec![*target])
+        }
+        Operation::Length { target, source } => (vector_objs(source), vec![*target]),
+        Operation::Blend { opacity, .. } => (vec![*opacity], vec![]),
+        Operation::Curve {
+            target,
+            operation,
+            source,
+            ..
+        } => {
+            let mut reads = vec![*source];
+            if reads_target(operation) {
+                reads.push(*target)
+            }
+            (reads, vec![*target])
+        }
+        Operation::Gradient {
+            target,
+            operation,
+            source,
+            ..
+        } => {
+            let mut reads = vec![*source];
+            if reads_target(operation) {
+                reads.extend(target)
+            }
+            (reads, target.to_vec())
+        }
+        Operation::Space(_)
+        | Operation::Goto(_)
+        | Operation::GotoTmp(_)
+        | Operation::Gamut(_)
+        | Operation::Rgb(_)
+        | Operation::Adapt { .. } => (vec![], vec![]),
+    };
+    // differences against variables read all three
+    let reads = reads
+        .into_iter()
+        .flat_map(|o| match o {
+            Obj::DeltaVars(_, vars) => vars.map(|v| Obj::Var(v as usize)).to_vec(),
+            o => vec![o],
+        })
+        .collect();
+    (reads, writes)
+}
+
+/// Sources that hold fractional values, so `==` on them rarely matches
+fn fractional(obj: &Obj) -> bool {
+    match obj {
+        Obj::Num(n) => n.fract() != 0.0,
+        Obj::Chan(_)
+        | Obj::E
+        | Obj::Pi
+        | Obj::Rand
+        | Obj::XNorm
+        | Obj::YNorm
+        | Obj::HK2023
+        | Obj::Mask
+        | Obj::Time
+        | Obj::Color(_)
+        | Obj::Delta(..)
+        | Obj::DeltaVars(..)
+        | Obj::Layer(_) => true,
+        Obj::Var(_)
+        | Obj::Row
+        | Obj::Col
+        | Obj::Width
+        | Obj::Height
+        | Obj::Frame
+        | Obj::Frames
+        | Obj::InGamut => false,
+    }
+}
+
+/// Warnings about variables, externals, operations that do nothing, and exact float comparisons.
+/// Loops are accounted for, other jumps are assumed to be skippable.
+fn lint(
+    operations: &[Operation],
+    lines: &[usize],
+    labels: &HashMap<String, usize>,
+) -> Vec<Warning> {
+    // {{{
+    let mut warnings = Vec::new();
+    let mut warn = |n: usize, kind: Lint, details: String| {
+        warnings.push(Warning {
+            line: lines[n],
+            kind,
+            details,
+        })
+    };
+
+    // (label, jump) of every backwards jump
+    let loops: Vec<(usize, usize)> = operations
+        .iter()
+        .enumerate()
+        .filter_map(|(n, o)| {
+            let label = match o {
+                Operation::GotoTmp(l) => l,
+                Operation::If { then, .. } => match then.as_ref() {
+                    Operation::GotoTmp(l) => l,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            labels.get(label).filter(|i| **i <= n).map(|i| (*i, n))
+        })
+        .collect();
+    let looped = |n: usize| loops.iter().filter(move |(l, j)| *l <= n && n <= *j);
+    let access: Vec<(Vec<Obj>, Vec<Obj>)> = operations.iter().map(accesses).collect();
+    let written_before = |n: usize, v: &Obj| {
+        (0..n)
+            .chain(looped(n).flat_map(|(_, j)| n..=*j))
+            .any(|i| access[i].1.contains(v))
+    };
+    let read_after = |n: usize, v: &Obj| {
+        (n + 1..operations.len())
+            .chain(looped(n).flat_map(|(l, _)| *l..=n))
+            .any(|i| access[i].0.contains(v))
+    };
+
+    let mut space = match operations.first() {
+        Some(Operation::Space(s)) => *s,
+        _ => return warnings,
+    };
+    let mut unwritten = Vec::<Obj>::new();
+    for (n, operation) in operations.iter().enumerate() {
+        let (reads, writes) = &access[n];
+        for v in reads {
+            if matches!(v, Obj::Var(i) if *i < 9) && !unwritten.contains(v) && !written_before(n, v)
+            {
+                unwritten.push(*v);
+                warn(
+                    n,
+                    Lint::Unset,
+                    format!("{} is read before it's set, so it's always 0", v),
+                );
+            }
+        }
+        for v in writes {
+            match v {
+                Obj::Var(i) if *i < 9 && !read_after(n, v) => {
+                    warn(n, Lint::Unused, format!("{} is set but never read", v))
+                }
+                Obj::Var(i) if *i >= 9 => warn(
+                    n,
+                    Lint::External,
+                    format!(
+                        "{} is an external input, use v1 through v9 for working values",
+                        v
+                    ),
+                ),
+                _ => (),
+            }
+        }
+
+        let no_effect = match operation {
+            Operation::Process {
+                target,
+                operation,
+                source,
+            } => match (operation, source) {
+                (Op::Add | Op::Sub, Obj::Num(n)) => *n == 0.0,
+                (Op::Mul | Op::Div | Op::Pow, Obj::Num(n)) => *n == 1.0,
+                (Op::Set | Op::Max | Op::Min, s) => s == target,
+                _ => false,
+            },
+            Operation::Swap { t1, t2 } => t1 == t2,
+            _ => false,
+        };
+        if no_effect {
+            warn(
+                n,
+                Lint::NoEffect,
+                format!("'{}' has no effect", op_source(operation, Some(space))),
+            );
+        }
+
+        if let Operation::If {
+            left,
+            cmp: cmp @ (Cmp::Eq | Cmp::NEq),
+            right,
+            ..
+        } = operation
+        {
+            if fractional(left) || fractional(right) {
+                warn(
+                    n,
+                    Lint::ExactCmp,
+                    format!(
+                        "'{} {} {}' compares fractional values exactly and rarely matches, \
+                        compare a range with < and > instead",
+                        obj_source(left, Some(space)),
+                        cmp,
+                        obj_source(right, Some(space))
+                    ),
+                );
+            }
+        }
+
+        match operation {
+            Operation::Space(s) => space = *s,
+            Operation::Rgb(_) => space = Space::LRGB,
+            _ => (),
+        }
+    }
+
+    warnings
+} // }}}
+
+pub fn parse_ops<S: AsRef<str>>(code: S, space: Space) -> (Vec<Operation>, Vec<OpError>) {
+    let (operations, errs, _) = parse_ops_ext(code, space);
+    (operations, errs)
+}
+
+/// Same as `parse_ops()` but also returns warnings about code that probably isn't what was meant.
+pub fn parse_ops_ext<S: AsRef<str>>(
+    code: S,
+    mut space: Space,
+) -> (Vec<Operation>, Vec<OpError>, Vec<Warning>) {
     // {{{
     let mut line = 0;
+    // line of each operation
+    let mut lines = vec![0];
+    let mut warnings = Vec::<Warning>::new();
+    // space before the last change and the line it changed on
+    let mut changed: Option<(Space, usize)> = None;
     let mut operations = Vec::<Operation>::new();
     let mut errs = Vec::<OpError>::new();
     let mut labels = HashMap::<String, usize>::new();
+    let mut tables = HashMap::<String, Table>::new();
     // initial Space
     operations.push(Operation::Space(space));
     let mut items = Vec::<&str>::new();
@@ -437,39 +2129,141 @@ pub fn parse_ops<S: AsRef<str>>(code: S, mut space: Space) -> (Vec<Operation>, V
             if row.starts_with('#') {
                 continue;
             } else if row.ends_with('\\') {
-                items.extend_from_slice(
-                    &row[0..row.len() - 1]
-                        .split_ascii_whitespace()
-                        .collect::<Vec<&str>>(),
-                );
+                items.extend_from_slice(&split_items(&row[0..row.len() - 1]));
                 continue;
-            } else if row.starts_with(":") {
-                labels.insert(row[1..].to_string(), operations.len());
+            } else if let Some(label) = row.strip_prefix(':') {
+                labels.insert(label.to_string(), operations.len());
             } else {
-                items.extend_from_slice(
-                    &row[0..row.len()]
-                        .split_ascii_whitespace()
-                        .collect::<Vec<&str>>(),
-                );
+                items.extend_from_slice(&split_items(row));
             }
             if items.is_empty() {
                 items = Vec::new();
                 continue;
             }
 
-            match parse_op(&items, &mut space, line) {
-                Ok(o) => operations.push(o),
-                Err(e) => errs.push(e),
+            if let Some(declared) = declaration(&items, line) {
+                if let Err(e) = declared.and_then(|t| declare(&mut tables, t, line)) {
+                    errs.push(e)
+                }
+                items = Vec::new();
+                continue;
+            }
+
+            let before = space;
+            match parse_op(&items, &mut space, line)
+                .and_then(|mut o| link_table(&mut o, &tables, line).map(|()| o))
+            {
+                Ok(o) => {
+                    operations.push(o);
+                    lines.push(line);
+                }
+                Err(e) => {
+                    // most likely a channel from before a space change
+                    if let Some((old, at)) = changed {
+                        let stale = items.iter().find_map(|i| match i.parse::<char>() {
+                            Ok(c)
+                                if old.channels().contains(&c)
+                                    && !space.channels().contains(&c) =>
+                            {
+                                Some(c)
+                            }
+                            _ => None,
+                        });
+                        if let Some(c) = stale {
+                            warnings.push(Warning {
+                                line,
+                                kind: Lint::Stale,
+                                details: format!(
+                                    "'{}' is a channel of {}, not {} which line {} switched to",
+                                    c,
+                                    space_source(old),
+                                    space_source(space),
+                                    at
+                                ),
+                            });
+                        }
+                    }
+                    errs.push(e)
+                }
+            }
+            if space != before {
+                changed = Some((before, line));
             }
 
             items = Vec::new();
         }
     }
 
+    warnings.extend(runaway_loops(&operations, &lines, &labels));
+    warnings.extend(lint(&operations, &lines, &labels));
+    warnings.sort_by_key(|w| w.line);
+
     operations = operations
         .into_iter()
         .filter_map(|o| goto_conv(Some(o), &labels))
         .collect();
+    eval_colors(&mut operations);
 
-    (operations, errs)
+    (operations, errs, warnings)
+} // }}}
+
+/// Rewrites `code` in canonical form: one statement per line, single spaces,
+/// symbols for arithmetic and comparisons like `+`, `**`, and `>=`, the first name of every other
+/// operator, channels named after the current space, and labels on their own line.
+/// Comments and single blank lines are kept. The result parses to the same operations.
+/// Colour literals are written as hex, so `tomato` becomes `#ff6347`.
+///
+/// Fails with the parse errors if any statement is invalid.
+pub fn format_code<S: AsRef<str>>(code: S, mut space: Space) -> Result<String, Vec<OpError>> {
+    // {{{
+    let mut out = Vec::<String>::new();
+    let mut errs = Vec::<OpError>::new();
+    let mut items = Vec::<String>::new();
+    let mut tables = HashMap::<String, Table>::new();
+    for (n, fullrow) in code.as_ref().trim().split('\n').enumerate() {
+        let line = n + 1;
+        if fullrow.trim().is_empty() && items.is_empty() {
+            if out.last().is_some_and(|l| !l.is_empty()) {
+                out.push(String::new());
+            }
+            continue;
+        }
+        for row in fullrow.split(';') {
+            if row.starts_with('#') {
+                out.push(row.trim_end().to_string());
+                continue;
+            }
+            let row = row.to_ascii_lowercase();
+            if let Some(continued) = row.strip_suffix('\\') {
+                items.extend(split_items(continued).into_iter().map(String::from));
+                continue;
+            } else if let Some(label) = row.strip_prefix(':') {
+                out.push(format!(":{}", label));
+            } else {
+                items.extend(split_items(&row).into_iter().map(String::from));
+            }
+            if items.is_empty() {
+                continue;
+            }
+            let before = space;
+            let refs: Vec<&str> = items.iter().map(|i| i.as_str()).collect();
+            let parsed = match declaration(&refs, line) {
+                Some(declared) => declared.and_then(|t| declare(&mut tables, t, line)),
+                None => parse_op(&refs, &mut space, line).and_then(|mut o| {
+                    link_table(&mut o, &tables, line).map(|()| op_source(&o, Some(before)))
+                }),
+            };
+            match parsed {
+                Ok(source) => out.push(source),
+                Err(e) => errs.push(e),
+            }
+            items.clear();
+        }
+    }
+
+    if errs.is_empty() {
+        Ok(out.join("\n") + "\n")
+    } else {
+        Err(errs)
+    }
 } // }}}
@@ -0,0 +1,366 @@
+//! RGB spaces besides sRGB, chromatic adaptation, and white balance, as matrices around CIE XYZ.
+//!
+//! XYZ is D65 relative with Y = 1.0 for white, same as colcon.
+
+use std::sync::OnceLock;
+
+use colcon::{srgb_eotf, srgb_oetf};
+
+type Mat = [[f64; 3]; 3];
+type Mat32 = [[f32; 3]; 3];
+
+// Matrix math {{{
+
+fn mul(m: &Mat, v: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|r| m[r][0] * v[0] + m[r][1] * v[1] + m[r][2] * v[2])
+}
+
+fn matmul(a: &Mat, b: &Mat) -> Mat {
+    [0, 1, 2].map(|r| [0, 1, 2].map(|c| (0..3).map(|k| a[r][k] * b[k][c]).sum()))
+}
+
+fn inv(m: &Mat) -> Mat {
+    let cof = |r: usize, c: usize| {
+        let (r1, r2) = ((r + 1) % 3, (r + 2) % 3);
+        let (c1, c2) = ((c + 1) % 3, (c + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+    let det = (0..3).map(|c| m[0][c] * cof(0, c)).sum::<f64>();
+    // transposed cofactors
+    [0, 1, 2].map(|r| [0, 1, 2].map(|c| cof(c, r) / det))
+}
+
+fn f32s(m: Mat) -> Mat32 {
+    m.map(|row| row.map(|v| v as f32))
+}
+
+fn apply<const N: usize>(m: &Mat32, p: &mut [f32; N]) {
+    let [x, y, z] = [p[0], p[1], p[2]];
+    for (r, row) in m.iter().enumerate() {
+        p[r] = row[0] * x + row[1] * y + row[2] * z;
+    }
+}
+
+/// XYZ of an xy chromaticity with Y = 1.0
+fn xy((x, y): (f64, f64)) -> [f64; 3] {
+    [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+// }}}
+
+/// White points for `adapt`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Illuminant {
+    A,
+    D50,
+    D55,
+    /// Approximately, as used by ACES
+    D60,
+    D65,
+    D75,
+    E,
+}
+
+impl Illuminant {
+    const ALL: [Illuminant; 7] = [
+        Illuminant::A,
+        Illuminant::D50,
+        Illuminant::D55,
+        Illuminant::D60,
+        Illuminant::D65,
+        Illuminant::D75,
+        Illuminant::E,
+    ];
+
+    fn xy(self) -> (f64, f64) {
+        match self {
+            Illuminant::A => (0.44757, 0.40745),
+            Illuminant::D50 => (0.34567, 0.35850),
+            Illuminant::D55 => (0.33242, 0.34743),
+            Illuminant::D60 => (0.32168, 0.33767),
+            Illuminant::D65 => (0.31270, 0.32900),
+            Illuminant::D75 => (0.29902, 0.31485),
+            Illuminant::E => (1.0 / 3.0, 1.0 / 3.0),
+        }
+    }
+}
+
+/// Cone response models for `adapt`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Cat {
+    Bradford,
+    Cat16,
+}
+
+impl Cat {
+    fn matrix(self) -> Mat {
+        match self {
+            Cat::Bradford => [
+                [0.8951, 0.2664, -0.1614],
+                [-0.7502, 1.7135, 0.0367],
+                [0.0389, -0.0685, 1.0296],
+            ],
+            Cat::Cat16 => [
+                [0.401288, 0.650173, -0.051461],
+                [-0.250268, 1.204414, 0.045854],
+                [-0.002079, 0.048952, 0.953127],
+            ],
+        }
+    }
+}
+
+/// Von Kries style adaptation of XYZ from one white chromaticity to another
+fn adaptation(from: (f64, f64), to: (f64, f64), cat: Cat) -> Mat {
+    let m = cat.matrix();
+    let (src, dst) = (mul(&m, xy(from)), mul(&m, xy(to)));
+    let scale = [0, 1, 2].map(|r| {
+        let mut row = [0.0; 3];
+        row[r] = dst[r] / src[r];
+        row
+    });
+    matmul(&inv(&m), &matmul(&scale, &m))
+}
+
+/// Adapts the XYZ colour `xyz` seen under `from` to how it would look under `to`.
+/// Channels after the third are untouched.
+pub fn adapt<const N: usize>(xyz: &mut [f32; N], from: Illuminant, to: Illuminant, cat: Cat) {
+    static TABLE: OnceLock<Vec<Mat32>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        [Cat::Bradford, Cat::Cat16]
+            .into_iter()
+            .flat_map(|cat| {
+                Illuminant::ALL.into_iter().flat_map(move |from| {
+                    Illuminant::ALL
+                        .into_iter()
+                        .map(move |to| f32s(adaptation(from.xy(), to.xy(), cat)))
+                })
+            })
+            .collect()
+    });
+    let n = Illuminant::ALL.len();
+    apply(
+        &table[(cat as usize * n + from as usize) * n + to as usize],
+        xyz,
+    );
+}
+
+// White balance {{{
+
+/// Colour temperature of D65, in kelvin
+const D65_CCT: f64 = 6504.0;
+
+/// Duv for a tint of 1.0
+const TINT_SCALE: f64 = 0.05;
+
+/// CIE 1960 uv of an xy chromaticity
+fn uv((x, y): (f64, f64)) -> (f64, f64) {
+    let d = -2.0 * x + 12.0 * y + 3.0;
+    (4.0 * x / d, 6.0 * y / d)
+}
+
+fn uv_xy((u, v): (f64, f64)) -> (f64, f64) {
+    let d = 2.0 * u - 8.0 * v + 4.0;
+    (3.0 * u / d, 2.0 * v / d)
+}
+
+/// Kelvin range where `locus()` fades from the Planckian fit into the daylight locus
+const LOCUS_BLEND: (f64, f64) = (3500.0, 4500.0);
+
+/// The CIE daylight locus. Defined from 4000K but smooth a little below
+fn daylight(t: f64) -> (f64, f64) {
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 7000.0 {
+        0.244063 + 0.09911e3 / t + 2.9678e6 / t2 - 4.6070e9 / t3
+    } else {
+        0.237040 + 0.24748e3 / t + 1.9018e6 / t2 - 2.0064e9 / t3
+    };
+    (x, -3.0 * x * x + 2.87 * x - 0.275)
+}
+
+/// Kim et al's fit of the Planckian locus
+fn planckian(t: f64) -> (f64, f64) {
+    let (t2, t3) = (t * t, t * t * t);
+    let x = -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910;
+    let y = if t <= 2222.0 {
+        -1.1063814 * x * x * x - 1.34811020 * x * x + 2.18555832 * x - 0.20219683
+    } else {
+        -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+    };
+    (x, y)
+}
+
+/// Chromaticity of a light at `kelvin`. The CIE daylight locus for daylight temperatures,
+/// and the Planckian locus for warmer lights where daylight isn't defined,
+/// faded between across `LOCUS_BLEND` so there's no jump.
+fn locus(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    let (lo, hi) = LOCUS_BLEND;
+    if t >= hi {
+        daylight(t)
+    } else if t <= lo {
+        planckian(t)
+    } else {
+        let (d, p) = (daylight(t), planckian(t));
+        let k = (t - lo) / (hi - lo);
+        (p.0 + (d.0 - p.0) * k, p.1 + (d.1 - p.1) * k)
+    }
+}
+
+/// White of a light at `kelvin`, moved off the locus toward green by `tint`
+fn white(kelvin: f64, tint: f64) -> (f64, f64) {
+    let (u, v) = uv(locus(kelvin));
+    if tint == 0.0 {
+        return uv_xy((u, v));
+    }
+    // perpendicular to the locus, which runs toward higher u as it gets warmer
+    let (u1, v1) = uv(locus(kelvin - 10.0));
+    let (u2, v2) = uv(locus(kelvin + 10.0));
+    let (du, dv) = (u1 - u2, v1 - v2);
+    let len = du.hypot(dv);
+    let (nu, nv) = (-dv / len, du / len);
+    // positive Duv is above the locus, which is green
+    let sign = if nv < 0.0 { -1.0 } else { 1.0 };
+    let duv = tint * TINT_SCALE * sign;
+    uv_xy((u + nu * duv, v + nv * duv))
+}
+
+/// Adapts the XYZ colour `xyz` lit by `from` to `to` with Bradford
+fn balance<const N: usize>(xyz: &mut [f32; N], from: (f64, f64), to: (f64, f64)) {
+    if !(from.0.is_finite() && from.1.is_finite() && from.1 > 0.0) || from == to {
+        return;
+    }
+    apply(&f32s(adaptation(from, to, Cat::Bradford)), xyz);
+}
+
+/// Corrects the XYZ colour `xyz` for a light at `kelvin`, making it bluer
+/// for warm lights and redder for cool ones.
+/// Adapts to the locus at 6504 rather than D65 itself, which is just off it,
+/// so 6504 leaves it as is.
+pub fn temperature<const N: usize>(xyz: &mut [f32; N], kelvin: f32) {
+    balance(xyz, white(kelvin as f64, 0.0), white(D65_CCT, 0.0))
+}
+
+/// Corrects the XYZ colour `xyz` for a light `tint` toward green,
+/// so positive values add magenta and negative ones add green.
+pub fn tint<const N: usize>(xyz: &mut [f32; N], tint: f32) {
+    balance(xyz, white(D65_CCT, tint as f64), white(D65_CCT, 0.0))
+}
+
+/// Adapts the XYZ colour `xyz` so the XYZ colour `neutral` becomes grey.
+/// Does nothing if `neutral` is black.
+pub fn whitepoint_from<const N: usize>(xyz: &mut [f32; N], neutral: [f32; 3]) {
+    let [x, y, z] = neutral.map(|c| c as f64);
+    let sum = x + y + z;
+    if sum > 0.0 {
+        balance(xyz, (x / sum, y / sum), Illuminant::D65.xy())
+    }
+}
+
+// }}}
+
+/// RGB working spaces other than sRGB. Channels are 'r' 'g' 'b' like sRGB.
+///
+/// Conversions leave channels after the third untouched, like colcon's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Rgb {
+    /// DCI-P3 primaries, D65, sRGB transfer
+    DisplayP3,
+    /// ITU-R BT.2020 primaries and transfer
+    Rec2020,
+    /// Adobe RGB (1998), D65, 2.2 gamma
+    AdobeRgb,
+    /// ACES AP1 primaries, ACES white, linear
+    AcesCg,
+}
+
+impl Rgb {
+    const ALL: [Rgb; 4] = [Rgb::DisplayP3, Rgb::Rec2020, Rgb::AdobeRgb, Rgb::AcesCg];
+
+    /// RGB to XYZ of the linear space
+    fn matrix(self) -> Mat {
+        let (primaries, white) = match self {
+            Rgb::DisplayP3 => (
+                [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)],
+                Illuminant::D65,
+            ),
+            Rgb::Rec2020 => (
+                [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046)],
+                Illuminant::D65,
+            ),
+            Rgb::AdobeRgb => (
+                [(0.640, 0.330), (0.210, 0.710), (0.150, 0.060)],
+                Illuminant::D65,
+            ),
+            Rgb::AcesCg => (
+                [(0.713, 0.293), (0.165, 0.830), (0.128, 0.044)],
+                Illuminant::D60,
+            ),
+        };
+        let columns = primaries.map(xy);
+        let p: Mat = [0, 1, 2].map(|r| [0, 1, 2].map(|c| columns[c][r]));
+        // scale primaries so 1, 1, 1 is white
+        let s = mul(&inv(&p), xy(white.xy()));
+        let m = [0, 1, 2].map(|r| [0, 1, 2].map(|c| p[r][c] * s[c]));
+        matmul(
+            &adaptation(white.xy(), Illuminant::D65.xy(), Cat::Bradford),
+            &m,
+        )
+    }
+
+    /// (to XYZ, from XYZ)
+    fn matrices(self) -> &'static (Mat32, Mat32) {
+        static TABLE: OnceLock<Vec<(Mat32, Mat32)>> = OnceLock::new();
+        &TABLE.get_or_init(|| {
+            Rgb::ALL
+                .into_iter()
+                .map(|rgb| {
+                    let m = rgb.matrix();
+                    (f32s(m), f32s(inv(&m)))
+                })
+                .collect()
+        })[self as usize]
+    }
+
+    /// Linear light to encoded. Negative values are mirrored
+    fn encode(self, c: f32) -> f32 {
+        const ALPHA: f32 = 1.099_297;
+        const BETA: f32 = 0.018_053_97;
+        match self {
+            Rgb::DisplayP3 => srgb_oetf(c),
+            Rgb::Rec2020 if c.abs() < BETA => c * 4.5,
+            Rgb::Rec2020 => c.signum() * (ALPHA * c.abs().powf(0.45) - (ALPHA - 1.0)),
+            Rgb::AdobeRgb => c.signum() * c.abs().powf(256.0 / 563.0),
+            Rgb::AcesCg => c,
+        }
+    }
+
+    fn decode(self, c: f32) -> f32 {
+        const ALPHA: f32 = 1.099_297;
+        const BETA: f32 = 0.018_053_97;
+        match self {
+            Rgb::DisplayP3 => srgb_eotf(c),
+            Rgb::Rec2020 if c.abs() < BETA * 4.5 => c / 4.5,
+            Rgb::Rec2020 => c.signum() * ((c.abs() + ALPHA - 1.0) / ALPHA).powf(1.0 / 0.45),
+            Rgb::AdobeRgb => c.signum() * c.abs().powf(563.0 / 256.0),
+            Rgb::AcesCg => c,
+        }
+    }
+
+    /// Converts an XYZ pixel into this space
+    pub fn from_xyz<const N: usize>(self, pixel: &mut [f32; N]) {
+        apply(&self.matrices().1, pixel);
+        pixel[..3].iter_mut().for_each(|c| *c = self.encode(*c));
+    }
+
+    /// Converts a pixel in this space to XYZ
+    pub fn to_xyz<const N: usize>(self, pixel: &mut [f32; N]) {
+        pixel[..3].iter_mut().for_each(|c| *c = self.decode(*c));
+        apply(&self.matrices().0, pixel);
+    }
+}
@@ -0,0 +1,104 @@
+//! Baking scripts into 3D LUTs.
+
+use pixelbuster::pbcore::{bake, parse_ops, BakeError, Lut3D, Params, Space};
+
+fn baked(code: &str, size: usize) -> Result<Lut3D, BakeError> {
+    let (ops, errs) = parse_ops(code, Space::SRGB);
+    assert!(errs.is_empty(), "{:?}", errs);
+    bake(ops, size, Space::SRGB, &Params::default())
+}
+
+/// Lattice point `i`, red changing fastest
+fn point(i: usize, size: usize) -> [f32; 3] {
+    [i % size, i / size % size, i / size / size].map(|c| c as f32 / (size - 1) as f32)
+}
+
+#[test]
+fn refuses() {
+    for code in [
+        "r = xnorm",
+        "g + col",
+        "b = rand",
+        "v1 = mask",
+        "r = frame",
+        "r * time",
+        "r = frames",
+        "r = l2.r",
+        "rgb mix l2.rgb 0.5",
+        "blend multiply",
+        "if r > 0.5 blend normal",
+        "if ynorm > 0.5 r = 1",
+        "v1 fbm r g 4 rand",
+    ] {
+        match baked(&format!("r * 2\n{}", code), 2) {
+            Err(BakeError::NotPerPixel { index, .. }) => assert_eq!(index, 2, "{}", code),
+            other => panic!("{} baked as {:?}", code, other.map(|l| l.size)),
+        }
+    }
+    assert_eq!(baked("r * 2", 1), Err(BakeError::Size(1)));
+    // noise is fine when it's driven by the colour alone
+    assert!(baked("v1 fbm r g 4 7\nb = v1\nif r > 0.5 g = 1", 2).is_ok());
+}
+
+#[test]
+fn identity_table() {
+    for code in ["", "oklch", "lrgb\nxyz"] {
+        let lut = baked(code, 5).unwrap();
+        assert_eq!(lut.table.len(), 125);
+        for (i, c) in lut.table.iter().enumerate() {
+            for (a, b) in c.iter().zip(point(i, 5)) {
+                assert!((a - b).abs() < 1e-4, "'{}' at {}: {:?}", code, i, c);
+            }
+        }
+    }
+}
+
+#[test]
+fn identity_cube() {
+    let cube = baked("", 3).unwrap().to_cube();
+    let mut lines = cube.lines().filter(|l| !l.starts_with('#'));
+    assert_eq!(lines.next(), Some("LUT_3D_SIZE 3"));
+    let rows: Vec<[f32; 3]> = lines
+        .map(|l| {
+            let v: Vec<f32> = l.split(' ').map(|v| v.parse().unwrap()).collect();
+            [v[0], v[1], v[2]]
+        })
+        .collect();
+    assert_eq!(rows.len(), 27);
+    for (i, row) in rows.iter().enumerate() {
+        for (a, b) in row.iter().zip(point(i, 3)) {
+            assert!((a - b).abs() < 1e-5, "row {}: {:?}", i, row);
+        }
+    }
+}
+
+#[test]
+fn identity_3dl() {
+    let lut = baked("", 3).unwrap().to_3dl();
+    let mut lines = lut.lines();
+    assert_eq!(lines.next(), Some("0 512 1023"));
+    let rows: Vec<&str> = lines.collect();
+    assert_eq!(rows.len(), 27);
+    // blue changes fastest, and output is 12 bit
+    let level = ["0", "2048", "4095"];
+    for (i, row) in rows.iter().enumerate() {
+        let expected = [i / 9, i / 3 % 3, i % 3].map(|c| level[c]).join(" ");
+        assert_eq!(*row, expected, "row {}", i);
+    }
+}
+
+#[test]
+fn identity_hald() {
+    let lut = baked("", 16).unwrap();
+    assert_eq!(lut.hald_level(), Some(4));
+    // the image is the table in reading order, 64 pixels wide
+    let (width, size) = (64, 16);
+    for (y, x) in [(0, 0), (0, 63), (5, 17), (63, 63)] {
+        let i = x + y * width;
+        let c = lut.table[i];
+        for (a, b) in c.iter().zip(point(i, size)) {
+            assert!((a - b).abs() < 1e-4, "({}, {}): {:?}", x, y, c);
+        }
+    }
+    assert_eq!(baked("", 17).unwrap().hald_level(), None);
+}
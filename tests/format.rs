@@ -0,0 +1,85 @@
+//! Formatted scripts parse back to the same program.
+
+use pixelbuster::pbcore::{format_code, parse_ops, Space};
+
+fn round_trip(code: &str, space: Space) -> String {
+    let formatted = format_code(code, space).unwrap();
+    let (before, errs) = parse_ops(code, space);
+    assert!(errs.is_empty(), "{:?}", errs);
+    let (after, errs) = parse_ops(&formatted, space);
+    assert!(errs.is_empty(), "{:?} in\n{}", errs, formatted);
+    assert_eq!(before, after, "\n{}\nformatted as\n{}", code, formatted);
+    assert_eq!(format_code(&formatted, space).unwrap(), formatted);
+    formatted
+}
+
+#[test]
+fn process() {
+    round_trip(
+        "r += 0.5\nc2 mul pi\nv1 = e2\ne9 sqrt rand\nb max xnorm",
+        Space::SRGB,
+    );
+    round_trip("l = hk2023\nh + 30", Space::OKLCH);
+}
+
+#[test]
+fn control() {
+    round_trip(
+        ":top\nv1 + 1\nif v1 lt 3 goto top\nif r != g jmp end\nswap g b\n:end\nif c4 gteq 0.5 r = 1",
+        Space::SRGB,
+    );
+}
+
+#[test]
+fn spaces() {
+    round_trip("lrgb\nr * 2\noklch\nc / 2\nsrgb\ngamut clip", Space::SRGB);
+    round_trip("acescg\nr * 2\nadapt d60 d65 cat16", Space::SRGB);
+}
+
+#[test]
+fn noise_and_white() {
+    round_trip(
+        "v1 fbm col row 4 rand\nv2 worley xnorm ynorm 3",
+        Space::SRGB,
+    );
+    round_trip(
+        "temperature 5500\ntint e2\nwhitepoint_from e1 e2 e3",
+        Space::SRGB,
+    );
+}
+
+#[test]
+fn vectors_and_colours() {
+    assert_eq!(
+        round_trip("r = tomato\nv1 = deltae2000(tomato)", Space::SRGB),
+        "r = #ff6347\nv1 = deltae2000(#ff6347)\n"
+    );
+    round_trip(
+        "rgb mix #ff0000 v1\nv1 dot rgb v4v5v6\nv2 = deltaok(v1v2v3)\nv1v2v3 = rgb\nv4 length v1v2v3",
+        Space::SRGB,
+    );
+}
+
+#[test]
+fn layers_and_tables() {
+    round_trip(
+        "r = l2.g\nrgb mix l2.rgb 0.5\nblend softlight e1\nblend multiply",
+        Space::SRGB,
+    );
+    round_trip(
+        "curve tone cubic 0,0 0.5,0.7 1,1\ngradient heat 0,black 0.5,tomato 1,#ffffff\nr = tone(r)\nv1 = tone(g)\nrgb = heat(v1)",
+        Space::SRGB,
+    );
+}
+
+#[test]
+fn comments_and_blanks() {
+    let formatted = round_trip(
+        "# setup\nv1 = 1\n\n\n\n# main\nr   +   v1 ; g + v1\n\n\nb + v1",
+        Space::SRGB,
+    );
+    assert_eq!(
+        formatted,
+        "# setup\nv1 = 1\n\n# main\nr + v1\ng + v1\n\nb + v1\n"
+    );
+}
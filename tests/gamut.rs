@@ -0,0 +1,55 @@
+//! Gamut mapping leaves displayable colours alone and brings the rest inside.
+
+use colcon::convert_space;
+use pixelbuster::pbcore::{gamut, Space};
+
+/// sRGB colours on a lattice over the whole gamut, including its corners
+fn lattice() -> impl Iterator<Item = [f32; 3]> {
+    (0..6 * 6 * 6).map(|i| [i % 6, i / 6 % 6, i / 36].map(|c| c as f32 / 5.0))
+}
+
+const MAPPINGS: [fn(&mut [f32; 3], Space); 3] = [gamut::clip, gamut::chroma, gamut::project];
+
+#[test]
+fn in_gamut_round_trip() {
+    for space in [Space::SRGB, Space::LRGB, Space::CIELAB, Space::OKLCH] {
+        for srgb in lattice() {
+            let mut pixel = srgb;
+            convert_space(Space::SRGB, space, &mut pixel);
+            assert!(gamut::in_gamut(&pixel, space), "{:?}", srgb);
+            for map in MAPPINGS {
+                let mut mapped = pixel;
+                map(&mut mapped, space);
+                convert_space(space, Space::SRGB, &mut mapped);
+                for (a, b) in mapped.iter().zip(srgb) {
+                    assert!(
+                        (a - b).abs() < 1e-4,
+                        "{:?} became {:?} in {}",
+                        srgb,
+                        mapped,
+                        space
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn out_of_gamut() {
+    // too chromatic, too bright, and too dark
+    for oklch in [[0.7, 0.4, 140.0], [1.2, 0.1, 20.0], [-0.1, 0.05, 250.0]] {
+        assert!(!gamut::in_gamut(&oklch, Space::OKLCH));
+        for map in MAPPINGS {
+            let mut mapped = oklch;
+            map(&mut mapped, Space::OKLCH);
+            assert!(gamut::in_gamut(&mapped, Space::OKLCH), "{:?}", mapped);
+        }
+        let mut mapped = oklch;
+        gamut::chroma(&mut mapped, Space::OKLCH);
+        if (0.0..=1.0).contains(&oklch[0]) {
+            assert!((mapped[0] - oklch[0]).abs() < 1e-3, "{:?}", mapped);
+            assert!((mapped[2] - oklch[2]).abs() < 1e-2, "{:?}", mapped);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Warnings from the linter, each with a script that should and shouldn't trigger it.
+
+use pixelbuster::pbcore::{parse_ops_ext, Lint, Space};
+
+/// Lines with a warning of `kind`
+fn lint(code: &str, kind: Lint) -> Vec<usize> {
+    let (_, errs, warnings) = parse_ops_ext(code, Space::SRGB);
+    assert!(errs.is_empty(), "{:?}", errs);
+    warnings
+        .iter()
+        .filter(|w| w.kind == kind)
+        .map(|w| w.line)
+        .collect()
+}
+
+#[test]
+fn unused() {
+    assert_eq!(lint("v1 = r\nv2 = g\nb = v2", Lint::Unused), [1]);
+    assert_eq!(lint("v1 = r\nv1 * 2\ng = v1", Lint::Unused), []);
+    // read again by the loop
+    assert_eq!(
+        lint(":a\nr + v1\nv1 = 0.1\nif r < 1 jmp a", Lint::Unused),
+        []
+    );
+}
+
+#[test]
+fn unset() {
+    assert_eq!(lint("r = v1\nv1 = 1", Lint::Unset), [1]);
+    assert_eq!(lint("v1 = 1\nr = v1", Lint::Unset), []);
+    assert_eq!(lint("r = e1", Lint::Unset), []);
+}
+
+#[test]
+fn no_effect() {
+    assert_eq!(
+        lint("r + 0\ng * 1\nb = b\nswap r r\nv1 max v1", Lint::NoEffect),
+        [1, 2, 3, 4, 5]
+    );
+    assert_eq!(
+        lint("r + 0.1\ng * 2\nb = r\nswap r g\nr max g", Lint::NoEffect),
+        []
+    );
+}
+
+#[test]
+fn exact_cmp() {
+    assert_eq!(
+        lint(
+            "if r == 0.5 g = 1\nif r != xnorm g = 1\nif r == g b = 1",
+            Lint::ExactCmp
+        ),
+        [1, 2, 3]
+    );
+    assert_eq!(
+        lint(
+            "v1 = col\nif v1 == 4 g = 1\nif row != 2 g = 1\nif r > 0.5 g = 1\nif r < xnorm g = 1",
+            Lint::ExactCmp
+        ),
+        []
+    );
+}
@@ -0,0 +1,107 @@
+//! Curve and gradient lookups.
+
+use pixelbuster::pbcore::{parse_ops, process, Curve, Gradient, Interp, Space};
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-3
+}
+
+#[test]
+fn curve() {
+    for interp in [Interp::Linear, Interp::Cubic] {
+        let curve = Curve::new(
+            "c".to_string(),
+            interp,
+            vec![[0.0, 0.1], [0.5, 0.3], [1.0, 0.9]],
+        );
+        assert!(close(curve.sample(0.0), 0.1), "{:?}", interp);
+        assert!(close(curve.sample(0.5), 0.3), "{:?}", interp);
+        assert!(close(curve.sample(1.0), 0.9), "{:?}", interp);
+        // level past the ends
+        assert_eq!(curve.sample(-2.0), curve.sample(0.0));
+        assert_eq!(curve.sample(5.0), curve.sample(1.0));
+        assert!(!curve.sample(f32::INFINITY).is_nan());
+    }
+    let linear = Curve::new(
+        "c".to_string(),
+        Interp::Linear,
+        vec![[0.2, 0.0], [0.6, 1.0]],
+    );
+    assert!(close(linear.sample(0.4), 0.5));
+    assert!(close(linear.sample(0.1), 0.0));
+    assert!(close(linear.sample(0.9), 1.0));
+}
+
+#[test]
+fn gradient() {
+    let gradient = Gradient::new(
+        "g".to_string(),
+        vec![(0.0, [0.0, 0.0, 0.0]), (1.0, [1.0, 1.0, 1.0])],
+    );
+    let grey = gradient.sample(0.5);
+    assert!(grey.iter().all(|c| close(*c, grey[0])), "{:?}", grey);
+    assert!(grey[0] > 0.3 && grey[0] < 0.7, "{:?}", grey);
+    for (x, end) in [(0.0, 0.0), (-1.0, 0.0), (1.0, 1.0), (3.0, 1.0)] {
+        let c = gradient.sample(x);
+        assert!(c.iter().all(|c| close(*c, end)), "{} {:?}", x, c);
+    }
+
+    // a hard edge from stops at the same place
+    let edge = Gradient::new(
+        "g".to_string(),
+        vec![
+            (0.0, [1.0, 0.0, 0.0]),
+            (0.5, [1.0, 0.0, 0.0]),
+            (0.5, [0.0, 0.0, 1.0]),
+            (1.0, [0.0, 0.0, 1.0]),
+        ],
+    );
+    assert!(close(edge.sample(0.45)[0], 1.0));
+    assert!(close(edge.sample(0.55)[2], 1.0));
+}
+
+#[test]
+fn lookups() {
+    let code = "curve half linear 0,0 1,0.5\ngradient bw 0,black 1,white\n\
+        v1 = r\nrgb = bw(v1)\nr = half(v1)\ng = half(g)";
+    let (ops, errs) = parse_ops(code, Space::SRGB);
+    assert!(errs.is_empty(), "{:?}", errs);
+    // out of range input reads as the nearest end
+    let mut pixels = vec![
+        -1.0, 0.0, 0.0, 1.0, //
+        0.0, 0.0, 0.0, 1.0, //
+        1.0, 0.0, 0.0, 1.0, //
+        2.0, 0.0, 0.0, 1.0,
+    ];
+    process(ops, &mut pixels, 4, None);
+    let expected = [
+        0.0, 0.0, 0.0, 1.0, //
+        0.0, 0.0, 0.0, 1.0, //
+        0.5, 0.5, 1.0, 1.0, //
+        0.5, 0.5, 1.0, 1.0,
+    ];
+    for (p, e) in pixels.iter().zip(expected) {
+        assert!(close(*p, e), "{:?}", pixels);
+    }
+}
+
+#[test]
+fn interpolation_words() {
+    let parse = |code: &str| parse_ops(code, Space::SRGB);
+    let (cubic, errs) = parse("curve t cubic 0,0 0.5,0.8 1,1\nr = t(r)");
+    assert!(errs.is_empty(), "{:?}", errs);
+    assert_eq!(
+        parse("curve t 0,0 0.5,0.8 1,1\nr = t(r)"),
+        (cubic, Vec::new())
+    );
+
+    let (_, errs) = parse("curve t cubci 0,0 1,1");
+    assert_eq!(errs.len(), 1);
+    assert!(
+        errs[0]
+            .to_string()
+            .contains("Unknown interpolation 'cubci'"),
+        "{}",
+        errs[0]
+    );
+}
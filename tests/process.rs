@@ -0,0 +1,457 @@
+//! Processing whole images through the public API.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use colcon::convert_space;
+use pixelbuster::pbcore::{
+    parse_ops, process_profile, process_progress, process_with, trace, Flow, Params, ProcessError,
+    Rect, Space,
+};
+
+/// A `width`×`height` sRGB ramp with varying alpha
+fn image(width: usize, height: usize) -> Vec<f32> {
+    (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = ((i % width) as f32, (i / width) as f32);
+            [
+                x / width as f32,
+                y / height as f32,
+                0.5,
+                1.0 - x / 2.0 / width as f32,
+            ]
+        })
+        .collect()
+}
+
+fn run(code: &str, pixels: &mut [f32], width: usize, params: &Params) {
+    let (ops, errs) = parse_ops(code, Space::SRGB);
+    assert!(errs.is_empty(), "{:?}", errs);
+    process_with(ops, pixels, width, params);
+}
+
+#[test]
+fn rand_ignores_threads() {
+    let code = "r = rand\ng + rand\nv1 = rand\nif v1 > 0.5 b = rand";
+    let params = Params {
+        seed: 7,
+        threads: 1,
+        ..Default::default()
+    };
+    let mut single = image(67, 41);
+    run(code, &mut single, 67, &params);
+
+    for threads in [2, 3, 8] {
+        let mut multi = image(67, 41);
+        run(code, &mut multi, 67, &Params { threads, ..params });
+        assert_eq!(single, multi, "{} threads", threads);
+    }
+
+    let mut reseeded = image(67, 41);
+    run(code, &mut reseeded, 67, &Params { seed: 8, ..params });
+    assert_ne!(single, reseeded);
+}
+
+#[test]
+fn roi() {
+    let code = "r = xnorm\ng = ynorm\nb = col\nv1 = row\nb + v1";
+    let roi = Rect {
+        x: 11,
+        y: 5,
+        width: 20,
+        height: 13,
+    };
+    let mut full = image(40, 30);
+    run(code, &mut full, 40, &Params::default());
+    let mut part = image(40, 30);
+    run(
+        code,
+        &mut part,
+        40,
+        &Params {
+            roi: Some(roi),
+            ..Default::default()
+        },
+    );
+
+    let orig = image(40, 30);
+    for (i, ((p, f), o)) in part
+        .chunks_exact(4)
+        .zip(full.chunks_exact(4))
+        .zip(orig.chunks_exact(4))
+        .enumerate()
+    {
+        let (x, y) = (i % 40, i / 40);
+        if (roi.x..roi.x + roi.width).contains(&x) && (roi.y..roi.y + roi.height).contains(&y) {
+            assert_eq!(p, f, "({}, {}) inside", x, y);
+        } else {
+            assert_eq!(p, o, "({}, {}) outside", x, y);
+        }
+    }
+}
+
+#[test]
+fn layer_follows_space() {
+    let layer = image(12, 10);
+    let mut pixels = vec![0.0; layer.len()];
+    // reads before and after each change of space, and the same channel twice
+    run(
+        "r = l2.r\nr + l2.r\noklch\nv1 = l2.h\nv2 = l2.l\nsrgb\ng = l2.g\nb = v1\nc4 = v2",
+        &mut pixels,
+        12,
+        &Params {
+            layer: Some(&layer),
+            ..Default::default()
+        },
+    );
+    for (p, l) in pixels.chunks_exact(4).zip(layer.chunks_exact(4)) {
+        let mut lch = [l[0], l[1], l[2]];
+        convert_space(Space::SRGB, Space::OKLCH, &mut lch);
+        // the pixel itself went through oklch and back
+        for (a, b) in p.iter().zip([l[0] * 2.0, l[1], lch[2], lch[0]]) {
+            assert!((a - b).abs() < 1e-4, "{:?} from {:?}", p, l);
+        }
+    }
+}
+
+#[test]
+fn progress() {
+    let (ops, _) = parse_ops("r = 1", Space::SRGB);
+    for threads in [1, 4] {
+        // several tiles per thread
+        let mut pixels = image(300, 250);
+        let reported = Mutex::new(Vec::new());
+        let result = process_progress(
+            &ops,
+            &mut pixels,
+            300,
+            &Params {
+                threads,
+                ..Default::default()
+            },
+            |p| reported.lock().unwrap().push(p),
+            &AtomicBool::new(false),
+        );
+        assert_eq!(result, Ok(0));
+        let mut reported = reported.into_inner().unwrap();
+        assert!(reported.len() > threads, "{:?}", reported);
+        reported.sort_by(f32::total_cmp);
+        assert!(reported[0] > 0.0);
+        assert_eq!(reported.last(), Some(&1.0));
+        assert!(pixels.chunks_exact(4).all(|p| p[0] == 1.0));
+    }
+}
+
+#[test]
+fn cancel() {
+    let (ops, _) = parse_ops("r = 1", Space::SRGB);
+    let orig = image(300, 250);
+
+    let mut pixels = orig.clone();
+    let cancel = AtomicBool::new(true);
+    let result = process_progress(&ops, &mut pixels, 300, &Params::default(), |_| (), &cancel);
+    assert_eq!(result, Err(ProcessError::Cancelled));
+    assert_eq!(pixels, orig);
+
+    // cancelled after the first tile finishes
+    let mut pixels = orig.clone();
+    let cancel = AtomicBool::new(false);
+    let result = process_progress(
+        &ops,
+        &mut pixels,
+        300,
+        &Params {
+            threads: 1,
+            ..Default::default()
+        },
+        |_| cancel.store(true, Ordering::Relaxed),
+        &cancel,
+    );
+    assert_eq!(result, Err(ProcessError::Cancelled));
+    let done = pixels.chunks_exact(4).take_while(|p| p[0] == 1.0).count();
+    assert!(done > 0 && done < 300 * 250, "{} done", done);
+    assert_eq!(pixels[done * 4..], orig[done * 4..]);
+}
+
+#[test]
+fn mask() {
+    let code = "r = 1\ng = mask";
+    let mut full = image(20, 10);
+    run(code, &mut full, 20, &Params::default());
+    // none, halfway, and all of the way across each row
+    let mask: Vec<f32> = (0..200).map(|i| [0.0, 0.5, 1.0][i % 20 / 7]).collect();
+    let mut masked = image(20, 10);
+    run(
+        code,
+        &mut masked,
+        20,
+        &Params {
+            mask: Some(&mask),
+            ..Default::default()
+        },
+    );
+
+    let orig = image(20, 10);
+    for (i, ((m, f), o)) in masked
+        .chunks_exact(4)
+        .zip(full.chunks_exact(4))
+        .zip(orig.chunks_exact(4))
+        .enumerate()
+    {
+        let k = mask[i];
+        assert_eq!(m[0], o[0] + (f[0] - o[0]) * k, "pixel {}", i);
+        // reads the mask, then is blended by it
+        assert_eq!(m[1], o[1] + (k - o[1]) * k, "pixel {}", i);
+        assert_eq!(m[2..], o[2..]);
+    }
+    // without a mask the source reads 1.0
+    assert!(full.chunks_exact(4).all(|p| p[1] == 1.0));
+}
+
+#[test]
+fn traced() {
+    let (ops, _) = parse_ops(":top\nv1 + 1\nif v1 < 3 goto top\nr = v1", Space::SRGB);
+    let pixels = image(8, 6);
+    let (steps, pixel) = trace(&ops, &pixels, 8, 5, 2, &Params::default()).unwrap();
+
+    let top = match steps.iter().find_map(|s| match s.flow {
+        Flow::Jump(i) => Some(i),
+        _ => None,
+    }) {
+        Some(i) => i,
+        None => panic!("no jump in {:?}", steps),
+    };
+    let flow: Vec<Flow> = steps
+        .iter()
+        .map(|s| s.flow)
+        .filter(|f| *f != Flow::Next)
+        .collect();
+    assert_eq!(
+        flow,
+        [
+            Flow::If(true),
+            Flow::Jump(top),
+            Flow::If(true),
+            Flow::Jump(top),
+            Flow::If(false)
+        ]
+    );
+    assert_eq!(steps.iter().map(|s| s.vars[0]).fold(0.0, f32::max), 3.0);
+    assert_eq!(pixel[0], 3.0);
+
+    // the same pixel as processing the whole image
+    let mut full = pixels.clone();
+    process_with(&ops, &mut full, 8, &Params::default());
+    assert_eq!(full[(5 + 2 * 8) * 4..][..4], pixel);
+
+    assert_eq!(trace(&ops, &pixels, 8, 8, 2, &Params::default()), None);
+    assert_eq!(trace(&ops, &pixels, 8, 5, 6, &Params::default()), None);
+}
+
+#[test]
+fn profile() {
+    let (ops, _) = parse_ops(
+        ":top\nv1 + 1\nif v1 < 3 goto top\nif xnorm < 0.5 r = 1",
+        Space::SRGB,
+    );
+    assert_eq!(ops.len(), 4, "{:?}", ops);
+    for threads in [1, 3] {
+        let mut pixels = image(300, 250);
+        let profile = process_profile(
+            &ops,
+            &mut pixels,
+            300,
+            &Params {
+                threads,
+                ..Default::default()
+            },
+        );
+        let n = 300 * 250;
+        // the loop runs three times, and the taken `if` isn't counted twice
+        assert_eq!(profile.counts, [n, n * 3, n * 3, n]);
+        assert_eq!(profile.pixels, 300 * 250);
+        assert_eq!(profile.jump_limited, 0);
+    }
+
+    let mut pixels = image(30, 20);
+    let profile = process_profile(
+        &ops,
+        &mut pixels,
+        30,
+        &Params {
+            jump_limit: Some(1),
+            ..Default::default()
+        },
+    );
+    assert_eq!(profile.counts, [600, 1200, 1200, 0]);
+    assert_eq!(profile.jump_limited, 600);
+}
+
+#[test]
+fn jump_limit() {
+    // pixels in the left half loop until they're stopped
+    let (ops, _) = parse_ops(":top\nr + 0.125\nif xnorm < 0.5 goto top", Space::SRGB);
+    let run = |params: &Params| {
+        let mut pixels = image(40, 30);
+        let result = process_progress(
+            &ops,
+            &mut pixels,
+            40,
+            params,
+            |_| (),
+            &AtomicBool::new(false),
+        );
+        (result, pixels)
+    };
+
+    let (result, pixels) = run(&Params::default());
+    assert_eq!(result, Ok(20 * 30));
+    let (result, limited) = run(&Params {
+        jump_limit: Some(3),
+        ..Default::default()
+    });
+    assert_eq!(result, Ok(20 * 30));
+    let orig = image(40, 30);
+    for (i, ((p, l), o)) in pixels
+        .chunks_exact(4)
+        .zip(limited.chunks_exact(4))
+        .zip(orig.chunks_exact(4))
+        .enumerate()
+    {
+        // the default allows 100 jumps, each adding once more
+        let runs = if i % 40 < 20 {
+            (101.0, 4.0)
+        } else {
+            (1.0, 1.0)
+        };
+        assert_eq!(p[0], o[0] + 0.125 * runs.0, "pixel {}", i);
+        assert_eq!(l[0], o[0] + 0.125 * runs.1, "pixel {}", i);
+    }
+
+    // still processes every pixel
+    let (result, errored) = run(&Params {
+        jump_limit: Some(3),
+        jump_limit_error: true,
+        ..Default::default()
+    });
+    assert_eq!(result, Err(ProcessError::JumpLimit(20 * 30)));
+    assert_eq!(errored, limited);
+    let (result, _) = run(&Params {
+        jump_limit: Some(0),
+        jump_limit_error: true,
+        ..Default::default()
+    });
+    assert_eq!(result, Err(ProcessError::JumpLimit(20 * 30)));
+
+    // only pixels that are stopped count
+    let mut pixels = image(40, 30);
+    let (ops, _) = parse_ops(":top\nv1 + 1\nif v1 < 4 goto top", Space::SRGB);
+    let params = Params {
+        jump_limit: Some(3),
+        jump_limit_error: true,
+        ..Default::default()
+    };
+    let result = process_progress(
+        &ops,
+        &mut pixels,
+        40,
+        &params,
+        |_| (),
+        &AtomicBool::new(false),
+    );
+    assert_eq!(result, Ok(0));
+}
+
+/// `image()` with colour multiplied by alpha
+fn premultiplied(width: usize, height: usize) -> Vec<f32> {
+    let mut pixels = image(width, height);
+    for p in pixels.chunks_exact_mut(4) {
+        let alpha = p[3];
+        p[..3].iter_mut().for_each(|c| *c *= alpha)
+    }
+    pixels
+}
+
+#[test]
+fn premultiply() {
+    let params = Params {
+        premultiplied: true,
+        ..Default::default()
+    };
+    let orig = premultiplied(20, 10);
+
+    // through another space and back
+    let mut pixels = orig.clone();
+    run("oklch\nl + 0\nsrgb\nr + 0", &mut pixels, 20, &params);
+    for (p, o) in pixels.iter().zip(&orig) {
+        assert!((p - o).abs() < 5e-5, "{} from {}", p, o);
+    }
+
+    // the script sees straight colour, in its own pixel and the layer
+    let layer = premultiplied(20, 10);
+    let mut pixels = orig.clone();
+    run(
+        "r = 0.8\ng = l2.r\nb = c4",
+        &mut pixels,
+        20,
+        &Params {
+            layer: Some(&layer),
+            ..params
+        },
+    );
+    for ((p, o), l) in pixels
+        .chunks_exact(4)
+        .zip(orig.chunks_exact(4))
+        .zip(image(20, 10).chunks_exact(4))
+    {
+        let alpha = o[3];
+        assert_eq!(p[3], alpha);
+        assert!((p[0] - 0.8 * alpha).abs() < 1e-6, "{:?}", p);
+        assert!((p[1] - l[0] * alpha).abs() < 1e-6, "{:?} {:?}", p, l);
+        assert!((p[2] - alpha * alpha).abs() < 1e-6, "{:?}", p);
+    }
+
+    // fully transparent colour is left alone rather than divided by zero
+    let mut clear = vec![0.0, 0.0, 0.0, 0.0, 0.5, 0.25, 0.0, 0.5];
+    run("g + 0.1\nc4 = 1", &mut clear, 2, &params);
+    assert_eq!(clear, [0.0, 0.1, 0.0, 1.0, 1.0, 0.6, 0.0, 1.0]);
+    let mut clear = vec![0.0; 4];
+    run("r = 1", &mut clear, 1, &params);
+    assert_eq!(clear, [0.0; 4]);
+}
+
+#[test]
+fn lock_alpha() {
+    let orig = image(20, 10);
+    let mut pixels = orig.clone();
+    run(
+        "c4 = 0.5\nr = c4",
+        &mut pixels,
+        20,
+        &Params {
+            lock_alpha: true,
+            ..Default::default()
+        },
+    );
+    for (p, o) in pixels.chunks_exact(4).zip(orig.chunks_exact(4)) {
+        // the script still sees its own alpha
+        assert_eq!(p, [0.5, o[1], o[2], o[3]]);
+    }
+
+    // colour is premultiplied by the alpha it's kept at
+    let orig = premultiplied(20, 10);
+    let mut pixels = orig.clone();
+    run(
+        "c4 = 0.5",
+        &mut pixels,
+        20,
+        &Params {
+            lock_alpha: true,
+            premultiplied: true,
+            ..Default::default()
+        },
+    );
+    for (p, o) in pixels.iter().zip(&orig) {
+        assert!((p - o).abs() < 1e-6, "{} from {}", p, o);
+    }
+}
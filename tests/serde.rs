@@ -0,0 +1,84 @@
+//! The JSON layout of parsed programs. Changing any of these strings is a breaking change.
+#![cfg(feature = "serde")]
+
+use pixelbuster::pbcore::{parse_ops, OpError, Operation, Space};
+
+fn json(code: &str) -> String {
+    serde_json::to_string(&parse_ops(code, Space::SRGB).0).unwrap()
+}
+
+#[test]
+fn process() {
+    assert_eq!(
+        json("r + 0.5\nv1 = e2\ne9 sqrt pi"),
+        concat!(
+            r#"[{"space":"srgb"},"#,
+            r#"{"process":{"target":{"chan":0},"operation":"add","source":{"num":0.5}}},"#,
+            r#"{"process":{"target":{"var":0},"operation":"set","source":{"var":10}}},"#,
+            r#"{"process":{"target":{"var":17},"operation":"sqrt","source":"pi"}}]"#
+        )
+    );
+}
+
+#[test]
+fn control() {
+    assert_eq!(
+        json(":top\nif c4 >= xnorm jmp top\nswap g b\noklch"),
+        concat!(
+            r#"[{"space":"srgb"},"#,
+            r#"{"if":{"left":{"chan":3},"cmp":"gteq","right":"xnorm","then":{"goto":1}}},"#,
+            r#"{"swap":{"t1":{"chan":1},"t2":{"chan":2}}},"#,
+            r#"{"space":"oklch"}]"#
+        )
+    );
+}
+
+#[test]
+fn noise() {
+    assert_eq!(
+        json("v1 fbm col row 4 rand"),
+        concat!(
+            r#"[{"space":"srgb"},"#,
+            r#"{"noise":{"target":{"var":0},"noise":"fbm","x":"col","y":"row","#,
+            r#""octaves":{"num":4.0},"seed":"rand"}}]"#
+        )
+    );
+}
+
+#[test]
+fn layer() {
+    assert_eq!(
+        json("blend softlight 0.5\nr = l2.c4"),
+        concat!(
+            r#"[{"space":"srgb"},"#,
+            r#"{"blend":{"mode":"softlight","opacity":{"num":0.5}}},"#,
+            r#"{"process":{"target":{"chan":0},"operation":"set","source":{"layer":3}}}]"#
+        )
+    );
+}
+
+#[test]
+fn errors() {
+    let errs = parse_ops("r + 1\nr ++ 1\nnot a real statement", Space::SRGB).1;
+    assert_eq!(
+        serde_json::to_string(&errs).unwrap(),
+        r#"[{"partial":{"line":2,"details":"Invalid operator"}},{"unknown":{"line":3}}]"#
+    );
+}
+
+#[test]
+fn round_trip() {
+    let ops = parse_ops(
+        "lrgb\n:a\nv1 + 1\nif v1 < 3 jmp a\ncielch\nh = hk2023\nv2 worley xnorm ynorm 3\nacescg\nadapt d60 d65 cat16\ntemperature 5500\nwhitepoint_from e1 e2 e3\nr = #ff8800\nv1 = deltae2000(tomato)\nv2 = deltaok(v1v2v3)\nrgb mix #ff0000 v1\nv1 dot rgb v4v5v6\nsrgb\nr = l2.g\nrgb mix l2.rgb 0.5\nblend softlight e1\ncurve tone linear 0,0 1,0.5\ngradient heat 0,black 1,#ff8800\nv1 = tone(r)\nrgb = heat(v1)",
+        Space::HSV,
+    )
+    .0;
+    let text = serde_json::to_string(&ops).unwrap();
+    assert_eq!(serde_json::from_str::<Vec<Operation>>(&text).unwrap(), ops);
+
+    let errs = vec![OpError::Unknown { line: 1 }];
+    let text = serde_json::to_string(&errs).unwrap();
+    assert_eq!(serde_json::from_str::<Vec<OpError>>(&text).unwrap(), errs);
+
+    assert!(serde_json::from_str::<Operation>(r#"{"space":"nowhere"}"#).is_err());
+}
@@ -0,0 +1,89 @@
+//! Statements on three channels or variables at once.
+
+use colcon::convert_space;
+use pixelbuster::pbcore::{parse_ops, process, Space};
+
+const PIXEL: [f32; 4] = [0.2, 0.4, 0.6, 1.0];
+
+fn run(code: &str, pixel: [f32; 4]) -> [f32; 4] {
+    let (ops, errs) = parse_ops(code, Space::SRGB);
+    assert!(errs.is_empty(), "{:?}", errs);
+    let mut pixels = pixel.to_vec();
+    process(ops, &mut pixels, 1, None);
+    [pixels[0], pixels[1], pixels[2], pixels[3]]
+}
+
+fn assert_near(a: [f32; 4], b: [f32; 4]) {
+    for (x, y) in a.iter().zip(b) {
+        assert!((x - y).abs() < 1e-5, "{:?} != {:?}", a, b);
+    }
+}
+
+#[test]
+fn per_channel() {
+    assert_near(run("rgb * 0.5", PIXEL), [0.1, 0.2, 0.3, 1.0]);
+    assert_near(run("c123 + 0.1", PIXEL), [0.3, 0.5, 0.7, 1.0]);
+    assert_near(run("rgb - bgr", PIXEL), [-0.4, 0.0, 0.4, 1.0]);
+    // every channel reads its source before any is written
+    assert_near(run("bgr = rgb", PIXEL), [0.6, 0.4, 0.2, 1.0]);
+    assert_near(run("c412 = c123", PIXEL), [0.4, 0.6, 0.6, 0.2]);
+    assert_near(
+        run("v4v5v6 = rgb\nrgb = 0\nrgb = v6v5v4", PIXEL),
+        [0.6, 0.4, 0.2, 1.0],
+    );
+}
+
+#[test]
+fn mix() {
+    assert_near(run("rgb mix #ff0000 0.5", PIXEL), [0.6, 0.2, 0.3, 1.0]);
+    assert_near(run("rgb mix bgr 0", PIXEL), PIXEL);
+    assert_near(run("rgb mix bgr 1", PIXEL), [0.6, 0.4, 0.2, 1.0]);
+}
+
+#[test]
+fn products() {
+    assert_near(
+        run("v3 = 1\nrgb cross v1v2v3", PIXEL),
+        [0.4, -0.2, 0.0, 1.0],
+    );
+    // perpendicular to both
+    let crossed = run(
+        "v1v2v3 = rgb\nrgb cross 0.3\nv4 dot rgb v1v2v3\nr = v4",
+        PIXEL,
+    );
+    assert!(crossed[0].abs() < 1e-6, "{:?}", crossed);
+
+    assert_near(run("v1 dot rgb rgb\nr = v1", PIXEL), [0.56, 0.4, 0.6, 1.0]);
+    assert_near(
+        run("v1 length rgb\nr = v1", PIXEL),
+        [0.56f32.sqrt(), 0.4, 0.6, 1.0],
+    );
+}
+
+#[test]
+fn lch_channels() {
+    let mut lch = [PIXEL[0], PIXEL[1], PIXEL[2]];
+    convert_space(Space::SRGB, Space::OKLCH, &mut lch);
+    // 'chl' is channels rather than a c123 list
+    for (space, code) in [
+        ("oklch", "chl = lch\nclh + lch"),
+        ("cielch", "chl = lch\nclh + lch"),
+        ("jzczhz", "chj = jch\ncjh + jch"),
+    ] {
+        let (_, errs) = parse_ops(format!("{}\n{}", space, code), Space::SRGB);
+        assert!(errs.is_empty(), "{}: {:?}", space, errs);
+    }
+    assert_near(
+        run("oklch\nv1v2v3 = chl\nsrgb\nrgb = v1v2v3", PIXEL),
+        [lch[1], lch[2], lch[0], 1.0],
+    );
+    assert_near(
+        run(
+            "v1 = 0.1\nv2 = 0.2\nv3 = 0.3\noklch\nchl = v1v2v3\nv4v5v6 = lch\nsrgb\nrgb = v4v5v6",
+            PIXEL,
+        ),
+        [0.3, 0.1, 0.2, 1.0],
+    );
+    // still digits when they're all digits
+    assert!(!parse_ops("oklch\nc12 = lch", Space::SRGB).1.is_empty());
+}
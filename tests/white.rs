@@ -0,0 +1,69 @@
+//! White balance statements.
+
+use pixelbuster::pbcore::{parse_ops, process, Space};
+
+fn run(code: &str, pixel: [f32; 4]) -> [f32; 4] {
+    let mut pixels = pixel.to_vec();
+    process(parse_ops(code, Space::SRGB).0, &mut pixels, 1, None);
+    [pixels[0], pixels[1], pixels[2], pixels[3]]
+}
+
+#[test]
+fn temperature_is_smooth() {
+    let grey = [0.5, 0.5, 0.5, 1.0];
+    let d65 = run("temperature 6504", grey);
+    for (a, b) in d65.iter().zip(grey) {
+        assert!((a - b).abs() < 1e-6, "{:?}", d65);
+    }
+    // no jumps where the locus changes
+    let mut last = run("temperature 3000", grey);
+    for kelvin in (3010..8000).step_by(10) {
+        let next = run(&format!("temperature {}", kelvin), grey);
+        for (a, b) in next.iter().zip(last) {
+            assert!(
+                (a - b).abs() < 3e-3,
+                "{}K {:?} after {:?}",
+                kelvin,
+                next,
+                last
+            );
+        }
+        last = next;
+    }
+}
+
+#[test]
+fn tint() {
+    let grey = [0.5, 0.5, 0.5, 1.0];
+    let none = run("tint 0", grey);
+    for (a, b) in none.iter().zip(grey) {
+        assert!((a - b).abs() < 1e-6, "{:?}", none);
+    }
+    let magenta = run("tint 0.5", grey);
+    assert!(
+        magenta[1] < magenta[0] - 0.01 && magenta[1] < magenta[2] - 0.01,
+        "{:?}",
+        magenta
+    );
+    let green = run("tint -0.5", grey);
+    assert!(
+        green[1] > green[0] + 0.01 && green[1] > green[2] + 0.01,
+        "{:?}",
+        green
+    );
+    // the tint is kept apart from temperature
+    let warm = run("temperature 4000", grey);
+    let both = run("temperature 4000\ntint 0.5", grey);
+    assert!(both[1] < warm[1], "{:?} {:?}", both, warm);
+}
+
+#[test]
+fn whitepoint_from() {
+    let warm = [0.8, 0.6, 0.4, 1.0];
+    let fixed = run("whitepoint_from 0.8 0.6 0.4", warm);
+    assert!((fixed[0] - fixed[1]).abs() < 1e-3 && (fixed[1] - fixed[2]).abs() < 1e-3);
+    let black = run("whitepoint_from 0 0 0", warm);
+    for (a, b) in black.iter().zip(warm) {
+        assert!((a - b).abs() < 1e-5, "{:?}", black);
+    }
+}